@@ -3,11 +3,13 @@ import {
   ASSOCIATED_TOKEN_PROGRAM_ID,
   TOKEN_PROGRAM_ID,
   getAssociatedTokenAddressSync,
+  getMint,
 } from "@solana/spl-token";
 
 import {
   AccountMeta,
   Connection,
+  Ed25519Program,
   PublicKey,
   SYSVAR_INSTRUCTIONS_PUBKEY,
   SYSVAR_RENT_PUBKEY,
@@ -66,6 +68,12 @@ import {
 import { log } from "../tests/utils";
 import * as fs from "fs";
 
+export type ValidUntil =
+  | { slot: { 0: anchor.BN } }
+  | { timestamp: { 0: anchor.BN } };
+
+export type TradeItem = { mint: PublicKey; amount: anchor.BN };
+
 export class LockerSDK {
   umi: Umi;
   program: anchor.Program<Casier>;
@@ -80,6 +88,7 @@ export class LockerSDK {
   splAurorianCollection: anchor.web3.PublicKey;
   coreAurorianCollection: string;
   coreAuroriansHolder: string | anchor.web3.PublicKey;
+  namespace: number;
   constructor(
     connection: Connection,
     adminPk: PublicKey,
@@ -90,7 +99,8 @@ export class LockerSDK {
     coreAurorianCollection?: string,
     coreAuroriansHolder?: PublicKey,
     oldMintToSeq?: Record<string, number>,
-    seqToNewMint?: Record<number, string>
+    seqToNewMint?: Record<number, string>,
+    namespace?: number
   ) {
     this.connection = connection;
     this.umi = createUmi(connection);
@@ -107,8 +117,9 @@ export class LockerSDK {
       anchorProvider
     );
     this.adminPk = adminPk;
+    this.namespace = namespace ?? 0;
     const [configPDA] = PublicKey.findProgramAddressSync(
-      [anchor.utils.bytes.utf8.encode("config")],
+      [anchor.utils.bytes.utf8.encode("config"), this.namespaceSeed()],
       this.program.programId
     );
     this.configPDA = configPDA;
@@ -142,6 +153,181 @@ export class LockerSDK {
     }
   }
 
+  /**
+   * Extra seed of the namespace accounts, empty for namespace 0 so that its
+   * addresses are the ones predating namespaces.
+   */
+  namespaceSeed(): Buffer {
+    if (this.namespace === 0) {
+      return Buffer.alloc(0);
+    }
+    const seed = Buffer.alloc(4);
+    seed.writeUInt32LE(this.namespace);
+    return seed;
+  }
+
+  getLockerPDA(owner: PublicKey): PublicKey {
+    const [lockerPDA] = PublicKey.findProgramAddressSync(
+      [owner.toBuffer(), this.namespaceSeed()],
+      this.program.programId
+    );
+    return lockerPDA;
+  }
+
+  /**
+   * Additional locker of `owner`, usable anywhere the v2 locker from
+   * `getLockerPDA` is.
+   */
+  getLockerV3PDA(owner: PublicKey, index: number): PublicKey {
+    const indexSeed = Buffer.alloc(2);
+    indexSeed.writeUInt16LE(index);
+    const [lockerPDA] = PublicKey.findProgramAddressSync(
+      [
+        anchor.utils.bytes.utf8.encode("locker"),
+        owner.toBuffer(),
+        indexSeed,
+        this.namespaceSeed(),
+      ],
+      this.program.programId
+    );
+    return lockerPDA;
+  }
+
+  async initLockerV3Instruction(
+    owner: PublicKey,
+    index: number
+  ): Promise<TransactionInstruction> {
+    return this.program.methods
+      .initLockerV3(index)
+      .accounts({
+        config: this.configPDA,
+        locker: this.getLockerV3PDA(owner, index),
+        owner: owner,
+        systemProgram: SystemProgram.programId,
+      })
+      .instruction();
+  }
+
+  /**
+   * Guild sharing a locker, which is `getLockerPDA` of the guild address.
+   */
+  guildPDA(id: anchor.BN): PublicKey {
+    const [guildPDA] = PublicKey.findProgramAddressSync(
+      [
+        anchor.utils.bytes.utf8.encode("guild"),
+        id.toArrayLike(Buffer, "le", 8),
+        this.namespaceSeed(),
+      ],
+      this.program.programId
+    );
+    return guildPDA;
+  }
+
+  delegatePDA(lockerPDA: PublicKey, delegate: PublicKey): PublicKey {
+    const [delegatePDA] = PublicKey.findProgramAddressSync(
+      [
+        anchor.utils.bytes.utf8.encode("delegate"),
+        lockerPDA.toBuffer(),
+        delegate.toBuffer(),
+      ],
+      this.program.programId
+    );
+    return delegatePDA;
+  }
+
+  rentalPDA(lenderLockerPDA: PublicKey, mint: PublicKey): PublicKey {
+    const [rentalPDA] = PublicKey.findProgramAddressSync(
+      [
+        anchor.utils.bytes.utf8.encode("rental"),
+        lenderLockerPDA.toBuffer(),
+        mint.toBuffer(),
+      ],
+      this.program.programId
+    );
+    return rentalPDA;
+  }
+
+  balancePDA(lockerPDA: PublicKey, mint: PublicKey): PublicKey {
+    const [balancePDA] = PublicKey.findProgramAddressSync(
+      [
+        anchor.utils.bytes.utf8.encode("balance"),
+        lockerPDA.toBuffer(),
+        mint.toBuffer(),
+      ],
+      this.program.programId
+    );
+    return balancePDA;
+  }
+
+  mintPolicyPDA(mint: PublicKey): PublicKey {
+    const [mintPolicyPDA] = PublicKey.findProgramAddressSync(
+      [
+        anchor.utils.bytes.utf8.encode("mint_policy"),
+        mint.toBuffer(),
+        this.namespaceSeed(),
+      ],
+      this.program.programId
+    );
+    return mintPolicyPDA;
+  }
+
+  /**
+   * Collects the deposit and withdrawal fees of the namespace.
+   */
+  treasuryPDA(): PublicKey {
+    const [treasuryPDA] = PublicKey.findProgramAddressSync(
+      [anchor.utils.bytes.utf8.encode("treasury"), this.namespaceSeed()],
+      this.program.programId
+    );
+    return treasuryPDA;
+  }
+
+  treasuryTaPDA(mint: PublicKey): PublicKey {
+    const [treasuryTaPDA] = PublicKey.findProgramAddressSync(
+      [
+        anchor.utils.bytes.utf8.encode("treasury"),
+        mint.toBuffer(),
+        this.namespaceSeed(),
+      ],
+      this.program.programId
+    );
+    return treasuryTaPDA;
+  }
+
+  /**
+   * Authority to give to soft currency mints. Withdrawals of those mints pass
+   * this address in place of the burn token account.
+   */
+  mintAuthorityPDA(): PublicKey {
+    const [mintAuthorityPDA] = PublicKey.findProgramAddressSync(
+      [anchor.utils.bytes.utf8.encode("mint_authority"), this.namespaceSeed()],
+      this.program.programId
+    );
+    return mintAuthorityPDA;
+  }
+
+  /**
+   * Account withdrawals of `mint` take from: the `[mint, namespace]` pool, or
+   * `mintAuthorityPDA()` for mints the program mints itself.
+   */
+  async withdrawSourcePDA(mint: PublicKey): Promise<[PublicKey, number]> {
+    const mintAuthority = this.mintAuthorityPDA();
+    const mintInfo = await getMint(this.connection, mint).catch(() => null);
+    if (mintInfo?.mintAuthority?.equals(mintAuthority)) {
+      return PublicKey.findProgramAddressSync(
+        [
+          anchor.utils.bytes.utf8.encode("mint_authority"),
+          this.namespaceSeed(),
+        ],
+        this.program.programId
+      );
+    }
+    return PublicKey.findProgramAddressSync(
+      [mint.toBuffer(), this.namespaceSeed()],
+      this.program.programId
+    );
+  }
+
   /**
    * Put assets and amounts in order: core, pnft, spl
    * */
@@ -323,7 +509,8 @@ export class LockerSDK {
     coreNftCount: number,
     userPk: PublicKey,
     nonce: anchor.BN,
-    lockerPDA: PublicKey
+    lockerPDA: PublicKey,
+    validUntil: ValidUntil | null = null
   ): Promise<TransactionInstruction[]> {
     const ixs: TransactionInstruction[] = [];
     const coreIxs = await this.depositCoreInstruction(
@@ -334,7 +521,7 @@ export class LockerSDK {
     if (onlyCore) {
       ixs.push(
         await this.program.methods
-          .incNonce(nonce)
+          .incNonce(nonce, validUntil)
           .accounts({
             config: this.configPDA,
             locker: lockerPDA,
@@ -352,7 +539,10 @@ export class LockerSDK {
   async depositInstruction(
     unorderedMints: PublicKey[],
     userPk: PublicKey,
-    unorderedDepositAmounts: anchor.BN[]
+    unorderedDepositAmounts: anchor.BN[],
+    validUntil: ValidUntil | null = null,
+    lockUntil: anchor.BN | null = null,
+    locker: PublicKey | null = null
   ): Promise<TransactionInstruction[]> {
     const {
       orderedMints,
@@ -362,22 +552,19 @@ export class LockerSDK {
       nonCoreMetadata,
     } = await this.orderMints(unorderedMints, unorderedDepositAmounts);
 
-    const [lockerPDA] = PublicKey.findProgramAddressSync(
-      [userPk.toBuffer()],
-      this.program.programId
-    );
-    const lockerInitIx = await this.initLockerInstructionIfNeeded(
-      userPk,
-      lockerPDA
-    );
+    // v3 lockers are created with `initLockerV3Instruction` beforehand.
+    const lockerPDA = locker ?? this.getLockerPDA(userPk);
+    const lockerInitIx = locker
+      ? null
+      : await this.initLockerInstructionIfNeeded(userPk, lockerPDA);
     let nonce = new anchor.BN(0);
     const ixs: TransactionInstruction[] = [];
 
     if (lockerInitIx) {
       ixs.push(lockerInitIx);
     } else {
-      const locker = await this.program.account.locker.fetch(lockerPDA);
-      nonce = locker.space;
+      const lockerAccount = await this.program.account.locker.fetch(lockerPDA);
+      nonce = lockerAccount.space;
     }
 
     if (
@@ -390,7 +577,8 @@ export class LockerSDK {
           nonCoreMetadata[0],
           userPk,
           nonce,
-          lockerPDA
+          lockerPDA,
+          validUntil
         );
       ixs.push(...unverifyOldAndTransferNewIx);
       return ixs;
@@ -402,7 +590,8 @@ export class LockerSDK {
         coreNftCount,
         userPk,
         nonce,
-        lockerPDA
+        lockerPDA,
+        validUntil
       );
       ixs.push(...coreIxs);
     }
@@ -414,7 +603,9 @@ export class LockerSDK {
         userPk,
         depositAmounts.slice(coreNftCount),
         nonce,
-        lockerPDA
+        lockerPDA,
+        validUntil,
+        lockUntil
       );
       ixs.push(...standardAndPnftIxs);
     }
@@ -424,12 +615,13 @@ export class LockerSDK {
     nonCoreMetadata: { metadata: Metadata },
     userPk: anchor.web3.PublicKey,
     nonce: anchor.BN,
-    lockerPDA: PublicKey
+    lockerPDA: PublicKey,
+    validUntil: ValidUntil | null = null
   ): Promise<{ ixs: anchor.web3.TransactionInstruction[]; newMint: string }> {
     const ixs: anchor.web3.TransactionInstruction[] = [];
     ixs.push(
       await this.program.methods
-        .incNonce(nonce)
+        .incNonce(nonce, validUntil)
         .accounts({
           config: this.configPDA,
           locker: lockerPDA,
@@ -509,7 +701,9 @@ export class LockerSDK {
     userPk: PublicKey,
     depositAmounts: anchor.BN[],
     nonce: anchor.BN,
-    lockerPDA: PublicKey
+    lockerPDA: PublicKey,
+    validUntil: ValidUntil | null = null,
+    lockUntil: anchor.BN | null = null
   ): Promise<anchor.web3.TransactionInstruction[]> {
     const remainingAccounts: Array<AccountMeta> = [];
     const vaultBumps: Array<number> = [];
@@ -539,7 +733,7 @@ export class LockerSDK {
         this.program.programId
       );
       const [burnTa, burnBump] = PublicKey.findProgramAddressSync(
-        [mint.toBuffer()],
+        [mint.toBuffer(), this.namespaceSeed()],
         this.program.programId
       );
       vaultBumps.push(vaultBump);
@@ -565,6 +759,11 @@ export class LockerSDK {
         isWritable: true,
         isSigner: false,
       });
+      remainingAccounts.push({
+        pubkey: this.balancePDA(lockerPDA, mint),
+        isWritable: true,
+        isSigner: false,
+      });
       if (index < pnftCount) {
         const [metadataPda] = findMetadataPda(this.umi, {
           mint: fromWeb3JsPublicKey(mint),
@@ -611,16 +810,22 @@ export class LockerSDK {
           Buffer.from(vaultBumps),
           Buffer.from(burnBumps),
           pnftCount,
-          nonce
+          nonce,
+          validUntil,
+          lockUntil
         )
         .accounts({
           config: this.configPDA,
           locker: lockerPDA,
+          delegate: null,
           admin: this.adminPk,
           owner: userPk,
+          lockerOwner: userPk,
           systemProgram: SystemProgram.programId,
           tokenProgram: TOKEN_PROGRAM_ID,
           rent: SYSVAR_RENT_PUBKEY,
+          treasury: this.treasuryPDA(),
+          instructions: SYSVAR_INSTRUCTIONS_PUBKEY,
         })
         .remainingAccounts(remainingAccounts)
         .instruction()
@@ -645,6 +850,7 @@ export class LockerSDK {
    * @param unorderedWithdrawAmounts 
    * @param sameTxMintCreation If some mints are created in the same tx, they should be included here on top of unorderedMints.
       Mint creation txs should be included before the ixs returned by this function.
+   * @param validUntil Optional slot or unix timestamp after which the instructions are rejected.
    * @returns 
    */
   async withdrawInstruction(
@@ -652,7 +858,9 @@ export class LockerSDK {
     userPk: PublicKey,
     vaultOwners: PublicKey[],
     unorderedWithdrawAmounts: anchor.BN[],
-    sameTxMintCreation?: PublicKey[]
+    sameTxMintCreation?: PublicKey[],
+    validUntil: ValidUntil | null = null,
+    locker: PublicKey | null = null
   ): Promise<TransactionInstruction[]> {
     const {
       orderedMints,
@@ -667,20 +875,16 @@ export class LockerSDK {
 
     const ixs: TransactionInstruction[] = [];
 
-    const [lockerPDA] = PublicKey.findProgramAddressSync(
-      [userPk.toBuffer()],
-      this.program.programId
-    );
+    const lockerPDA = locker ?? this.getLockerPDA(userPk);
     let nonce = new anchor.BN(0);
-    const lockerInitIx = await this.initLockerInstructionIfNeeded(
-      userPk,
-      lockerPDA
-    );
+    const lockerInitIx = locker
+      ? null
+      : await this.initLockerInstructionIfNeeded(userPk, lockerPDA);
     if (lockerInitIx) {
       ixs.push(lockerInitIx);
     } else {
-      const locker = await this.program.account.locker.fetch(lockerPDA);
-      nonce = locker.space;
+      const lockerAccount = await this.program.account.locker.fetch(lockerPDA);
+      nonce = lockerAccount.space;
     }
 
     if (coreNftCount > 0) {
@@ -692,7 +896,7 @@ export class LockerSDK {
       if (onlyCore) {
         ixs.push(
           await this.program.methods
-            .incNonce(nonce)
+            .incNonce(nonce, validUntil)
             .accounts({
               config: this.configPDA,
               locker: lockerPDA,
@@ -713,7 +917,8 @@ export class LockerSDK {
       withdrawAmounts.slice(coreNftCount),
       nonce,
       lockerPDA,
-      vaultOwners
+      vaultOwners,
+      validUntil
     );
     ixs.push(...standardAndPnftIxs);
 
@@ -727,7 +932,8 @@ export class LockerSDK {
     withdrawAmounts: anchor.BN[],
     nonce: anchor.BN,
     lockerPDA: anchor.web3.PublicKey,
-    vaultOwners: PublicKey[]
+    vaultOwners: PublicKey[],
+    validUntil: ValidUntil | null = null
   ): Promise<TransactionInstruction[]> {
     const ixs: TransactionInstruction[] = [];
     const remainingAccounts: Array<AccountMeta> = [];
@@ -748,10 +954,7 @@ export class LockerSDK {
         });
       }
       const mint = mints[index];
-      const [burnTa, burnBump] = PublicKey.findProgramAddressSync(
-        [mint.toBuffer()],
-        this.program.programId
-      );
+      const [burnTa, burnBump] = await this.withdrawSourcePDA(mint);
       burnBumps.push(burnBump);
       remainingAccounts.push({
         pubkey: mint,
@@ -784,6 +987,21 @@ export class LockerSDK {
           pubkey: burnTa,
           isWritable: true,
           isSigner: false,
+        },
+        {
+          pubkey: this.balancePDA(lockerPDA, mint),
+          isWritable: true,
+          isSigner: false,
+        },
+        {
+          pubkey: this.mintPolicyPDA(mint),
+          isWritable: true,
+          isSigner: false,
+        },
+        {
+          pubkey: this.treasuryTaPDA(mint),
+          isWritable: true,
+          isSigner: false,
         }
       );
       vaultBumps.push(vaultBump);
@@ -832,18 +1050,23 @@ export class LockerSDK {
           Buffer.from(vaultBumps),
           Buffer.from(burnBumps),
           pnftCount,
-          nonce
+          nonce,
+          validUntil
         )
         .accounts({
           config: this.configPDA,
           locker: lockerPDA,
+          delegate: null,
           admin: this.adminPk,
           userTaOwner: userPk,
           vaultTaOwner: userPk,
+          lockerOwner: userPk,
           systemProgram: SystemProgram.programId,
           tokenProgram: TOKEN_PROGRAM_ID,
           associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
           rent: SYSVAR_RENT_PUBKEY,
+          treasury: this.treasuryPDA(),
+          instructions: SYSVAR_INSTRUCTIONS_PUBKEY,
         })
         .remainingAccounts(remainingAccounts)
         .instruction()
@@ -851,6 +1074,277 @@ export class LockerSDK {
     return ixs;
   }
 
+  /**
+   * Moves ledger balances between lockers. Either both owners or the admin must sign.
+   */
+  async transferBetweenLockersInstruction(
+    mints: PublicKey[],
+    fromOwner: PublicKey,
+    toOwner: PublicKey,
+    amounts: anchor.BN[],
+    payer: PublicKey
+  ): Promise<TransactionInstruction> {
+    const fromLocker = this.getLockerPDA(fromOwner);
+    const toLocker = this.getLockerPDA(toOwner);
+    const remainingAccounts: Array<AccountMeta> = mints.flatMap((mint) => [
+      { pubkey: mint, isWritable: false, isSigner: false },
+      {
+        pubkey: this.balancePDA(fromLocker, mint),
+        isWritable: true,
+        isSigner: false,
+      },
+      {
+        pubkey: this.balancePDA(toLocker, mint),
+        isWritable: true,
+        isSigner: false,
+      },
+    ]);
+    return this.program.methods
+      .transferBetweenLockers(amounts)
+      .accounts({
+        config: this.configPDA,
+        fromLocker,
+        toLocker,
+        fromOwner,
+        toOwner,
+        admin: this.adminPk,
+        payer,
+        systemProgram: SystemProgram.programId,
+      })
+      .remainingAccounts(remainingAccounts)
+      .instruction();
+  }
+
+  offerPDA(makerLocker: PublicKey, id: anchor.BN): PublicKey {
+    const [offerPDA] = PublicKey.findProgramAddressSync(
+      [
+        anchor.utils.bytes.utf8.encode("offer"),
+        makerLocker.toBuffer(),
+        id.toArrayLike(Buffer, "le", 8),
+      ],
+      this.program.programId
+    );
+    return offerPDA;
+  }
+
+  async createOfferInstruction(
+    maker: PublicKey,
+    id: anchor.BN,
+    give: TradeItem[],
+    want: TradeItem[],
+    expiry: anchor.BN,
+    taker?: PublicKey
+  ): Promise<TransactionInstruction> {
+    const makerLocker = this.getLockerPDA(maker);
+    return this.program.methods
+      .createOffer(
+        id,
+        give,
+        want,
+        taker ? this.getLockerPDA(taker) : null,
+        expiry
+      )
+      .accounts({
+        config: this.configPDA,
+        makerLocker,
+        offer: this.offerPDA(makerLocker, id),
+        owner: maker,
+        systemProgram: SystemProgram.programId,
+      })
+      .instruction();
+  }
+
+  async cancelOfferInstruction(
+    maker: PublicKey,
+    id: anchor.BN
+  ): Promise<TransactionInstruction> {
+    const makerLocker = this.getLockerPDA(maker);
+    return this.program.methods
+      .cancelOffer()
+      .accounts({
+        makerLocker,
+        offer: this.offerPDA(makerLocker, id),
+        owner: maker,
+      })
+      .instruction();
+  }
+
+  async acceptOfferInstruction(
+    maker: PublicKey,
+    id: anchor.BN,
+    taker: PublicKey
+  ): Promise<TransactionInstruction> {
+    const makerLocker = this.getLockerPDA(maker);
+    const takerLocker = this.getLockerPDA(taker);
+    const offer = this.offerPDA(makerLocker, id);
+    const { give, want } = await this.program.account.offer.fetch(offer);
+    const legs = (items: TradeItem[], from: PublicKey, to: PublicKey) =>
+      items.flatMap(({ mint }) => [
+        {
+          pubkey: this.balancePDA(from, mint),
+          isWritable: true,
+          isSigner: false,
+        },
+        {
+          pubkey: this.balancePDA(to, mint),
+          isWritable: true,
+          isSigner: false,
+        },
+      ]);
+    return this.program.methods
+      .acceptOffer(give, want)
+      .accounts({
+        config: this.configPDA,
+        offer,
+        makerLocker,
+        makerOwner: maker,
+        takerLocker,
+        owner: taker,
+        systemProgram: SystemProgram.programId,
+      })
+      .remainingAccounts([
+        ...legs(give as TradeItem[], makerLocker, takerLocker),
+        ...legs(want as TradeItem[], takerLocker, makerLocker),
+      ])
+      .instruction();
+  }
+
+  /**
+   * Message the admin signs off-chain to authorize a `redeemVoucher` withdrawal.
+   */
+  voucherMessage(
+    lockerPDA: PublicKey,
+    mint: PublicKey,
+    amount: anchor.BN,
+    nonce: anchor.BN,
+    expiry: anchor.BN
+  ): Buffer {
+    return Buffer.concat([
+      lockerPDA.toBuffer(),
+      mint.toBuffer(),
+      amount.toArrayLike(Buffer, "le", 8),
+      nonce.toArrayLike(Buffer, "le", 8),
+      expiry.toTwos(64).toArrayLike(Buffer, "le", 8),
+    ]);
+  }
+
+  /**
+   * @param signature Admin signature of `voucherMessage(lockerPDA, mint, amount, nonce, expiry)`.
+   * @returns The Ed25519 verification ix followed by the redeem ix, in that order.
+   */
+  async redeemVoucherInstruction(
+    mint: PublicKey,
+    userPk: PublicKey,
+    amount: anchor.BN,
+    nonce: anchor.BN,
+    expiry: anchor.BN,
+    signature: Uint8Array
+  ): Promise<TransactionInstruction[]> {
+    const { pnftCount } = await this.orderMints([mint], [amount]);
+    const [lockerPDA] = PublicKey.findProgramAddressSync(
+      [userPk.toBuffer(), this.namespaceSeed()],
+      this.program.programId
+    );
+    const [vaultTa, vaultBump] = PublicKey.findProgramAddressSync(
+      [mint.toBuffer(), userPk.toBuffer()],
+      this.program.programId
+    );
+    const [burnTa, burnBump] = await this.withdrawSourcePDA(mint);
+    const userTa = getAssociatedTokenAddressSync(mint, userPk);
+    const remainingAccounts: Array<AccountMeta> = [
+      {
+        pubkey: toWeb3JsPublicKey(MPL_TOKEN_METADATA_PROGRAM_ID),
+        isWritable: false,
+        isSigner: false,
+      },
+      {
+        pubkey: SYSVAR_INSTRUCTIONS_PUBKEY,
+        isWritable: false,
+        isSigner: false,
+      },
+      { pubkey: mint, isWritable: true, isSigner: false },
+      { pubkey: userTa, isWritable: true, isSigner: false },
+      { pubkey: vaultTa, isWritable: true, isSigner: false },
+      { pubkey: userPk, isWritable: true, isSigner: false },
+      { pubkey: burnTa, isWritable: true, isSigner: false },
+      {
+        pubkey: this.balancePDA(lockerPDA, mint),
+        isWritable: true,
+        isSigner: false,
+      },
+      {
+        pubkey: this.mintPolicyPDA(mint),
+        isWritable: true,
+        isSigner: false,
+      },
+      {
+        pubkey: this.treasuryTaPDA(mint),
+        isWritable: true,
+        isSigner: false,
+      },
+    ];
+    if (pnftCount > 0) {
+      const [metadataPda] = findMetadataPda(this.umi, {
+        mint: fromWeb3JsPublicKey(mint),
+      });
+      const [tokenRecordSender] = findTokenRecordPda(this.umi, {
+        mint: fromWeb3JsPublicKey(mint),
+        token: fromWeb3JsPublicKey(burnTa),
+      });
+      const [tokenRecordDestination] = findTokenRecordPda(this.umi, {
+        mint: fromWeb3JsPublicKey(mint),
+        token: fromWeb3JsPublicKey(userTa),
+      });
+      const [editionPk] = findMasterEditionPda(this.umi, {
+        mint: fromWeb3JsPublicKey(mint),
+      });
+      remainingAccounts.push(
+        {
+          pubkey: toWeb3JsPublicKey(metadataPda),
+          isWritable: true,
+          isSigner: false,
+        },
+        {
+          pubkey: toWeb3JsPublicKey(tokenRecordSender),
+          isWritable: true,
+          isSigner: false,
+        },
+        {
+          pubkey: toWeb3JsPublicKey(tokenRecordDestination),
+          isWritable: true,
+          isSigner: false,
+        },
+        {
+          pubkey: toWeb3JsPublicKey(editionPk),
+          isWritable: false,
+          isSigner: false,
+        }
+      );
+    }
+
+    return [
+      Ed25519Program.createInstructionWithPublicKey({
+        publicKey: this.adminPk.toBytes(),
+        message: this.voucherMessage(lockerPDA, mint, amount, nonce, expiry),
+        signature,
+      }),
+      await this.program.methods
+        .redeemVoucher(amount, vaultBump, burnBump, nonce, expiry)
+        .accounts({
+          config: this.configPDA,
+          locker: lockerPDA,
+          owner: userPk,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          rent: SYSVAR_RENT_PUBKEY,
+          treasury: this.treasuryPDA(),
+        })
+        .remainingAccounts(remainingAccounts)
+        .instruction(),
+    ];
+  }
+
   async withdrawCoreInstruction(
     mints: UmiPublicKey[],
     userPk: UmiPublicKey
@@ -948,6 +1442,17 @@ export class LockerSDK {
       exists = account?.exists;
     } catch (e) {}
 
+    if (!exists && this.namespace !== 0) {
+      return this.program.methods
+        .initNamespaceLocker()
+        .accounts({
+          config: this.configPDA,
+          locker: lockerPDA,
+          owner: owner,
+          systemProgram: SystemProgram.programId,
+        })
+        .instruction();
+    }
     if (!exists) {
       return (
         this.program.methods
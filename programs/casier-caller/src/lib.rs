@@ -0,0 +1,46 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+use anchor_lang::solana_program::program::invoke;
+use casier::program::Casier;
+
+declare_id!("9csdcRUWSHqAh7SvCb6ZYyXDaiWBf8bQGuPmcoYevHwj");
+
+/// Invokes casier through CPI, so that tests can exercise its CPI guard.
+#[program]
+pub mod casier_caller {
+
+    use super::*;
+
+    /// Forwards `data` to casier with the remaining accounts, signers
+    /// included, as the accounts of the casier instruction.
+    pub fn forward<'info>(
+        ctx: Context<'_, '_, '_, 'info, Forward<'info>>,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        let accounts = ctx
+            .remaining_accounts
+            .iter()
+            .map(|account| AccountMeta {
+                pubkey: account.key(),
+                is_signer: account.is_signer,
+                is_writable: account.is_writable,
+            })
+            .collect();
+        let mut account_infos = ctx.remaining_accounts.to_vec();
+        account_infos.push(ctx.accounts.casier.to_account_info());
+        invoke(
+            &Instruction {
+                program_id: casier::ID,
+                accounts,
+                data,
+            },
+            &account_infos,
+        )?;
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct Forward<'info> {
+    pub casier: Program<'info, Casier>,
+}
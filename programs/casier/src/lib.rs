@@ -1,12 +1,14 @@
 pub mod state;
 pub mod utils;
+#[cfg(feature = "cpi")]
+pub mod views;
 
 use crate::state::{ErrorCode, *};
 use crate::utils::*;
 use anchor_lang::prelude::*;
 use anchor_lang::solana_program::{pubkey::Pubkey, rent::Rent};
-use anchor_spl;
-use anchor_spl::{associated_token::AssociatedToken, token::Token};
+use anchor_lang::Discriminator;
+use mpl_token_metadata::instructions::TransferV1CpiBuilder;
 // declare_id!("CAsieqooSrgVxhgWRwh21gyjq7Rmuhmo4qTW9XzXtAvW");
 declare_id!("FLoc9nBwGb2ayzVzb5GC9NttuPY3CxMhd4KDnApr79Ab");
 
@@ -21,9 +23,344 @@ pub mod casier {
         Ok(())
     }
 
+    /// Creates the config of another game sharing this deployment. The root
+    /// admin picks its admin, after which the namespace is independent.
+    pub fn init_namespace_config(
+        ctx: Context<InitNamespaceConfig>,
+        namespace: u32,
+        admin: Pubkey,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.admin = admin;
+        config.namespace = namespace;
+        Ok(())
+    }
+
+    /// Grows a config created before fields were appended to `Config`, keeping
+    /// its admin and frozen state.
+    pub fn migrate_config(ctx: Context<MigrateConfig>) -> Result<()> {
+        let config = &ctx.accounts.config;
+        let admin = &ctx.accounts.admin;
+        if *config.owner != crate::ID || config.data_len() >= Config::SIZE {
+            return Err(error!(ErrorCode::InvalidBeforeState));
+        }
+        // Fields are only ever appended, zero padding yields their defaults.
+        let migrated = {
+            let data = config.try_borrow_data()?;
+            if data.len() < 8 + 32 + 1 || data[..8] != Config::DISCRIMINATOR {
+                return Err(error!(ErrorCode::InvalidBeforeState));
+            }
+            let mut padded = data.to_vec();
+            padded.resize(Config::SIZE, 0);
+            Config::try_deserialize(&mut &padded[..])?
+        };
+        if migrated.admin != admin.key() {
+            return Err(error!(ErrorCode::Unauthorized));
+        }
+
+        let required_lamports = Rent::get()?
+            .minimum_balance(Config::SIZE)
+            .saturating_sub(config.lamports());
+        if required_lamports > 0 {
+            anchor_lang::system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    anchor_lang::system_program::Transfer {
+                        from: admin.to_account_info(),
+                        to: config.to_account_info(),
+                    },
+                ),
+                required_lamports,
+            )?;
+        }
+        config.realloc(Config::SIZE, true)?;
+        migrated.try_serialize(&mut &mut config.try_borrow_mut_data()?[..])
+    }
+
+    /// The quarantine may be a multisig, clawed back items can then only leave
+    /// it with its approval.
+    /// Set once, so that the admin key alone cannot repoint clawbacks.
+    pub fn set_quarantine(ctx: Context<UpdateConfig>, quarantine: Pubkey) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        if config.quarantine != Pubkey::default() {
+            return Err(error!(ErrorCode::QuarantineAlreadySet));
+        }
+        config.quarantine = quarantine;
+        Ok(())
+    }
+
+    /// Restricts which programs may CPI into deposits, withdrawals and claims:
+    /// `Config::CPI_OPEN`, `CPI_ALLOWLIST` or `CPI_DISABLED`. By design the
+    /// allowlist matches the program of the top level instruction, not the
+    /// direct caller, which the instructions sysvar cannot see: allowing a
+    /// program trusts every program it invokes. See `check_cpi_caller`.
+    pub fn set_cpi_guard(
+        ctx: Context<UpdateConfig>,
+        cpi_guard: u8,
+        cpi_allowlist: Vec<Pubkey>,
+    ) -> Result<()> {
+        if cpi_guard > Config::CPI_DISABLED || cpi_allowlist.len() > Config::MAX_CPI_CALLERS {
+            return Err(error!(ErrorCode::InvalidCpiGuard));
+        }
+        let config = &mut ctx.accounts.config;
+        config.cpi_guard = cpi_guard;
+        config.cpi_allowlist = cpi_allowlist;
+        Ok(())
+    }
+
+    /// Lamports charged per item by `deposit_batch` and `withdraw_v2_batch`.
+    pub fn set_fees(ctx: Context<UpdateConfig>, deposit_fee: u64, withdraw_fee: u64) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.deposit_fee = deposit_fee;
+        config.withdraw_fee = withdraw_fee;
+        Ok(())
+    }
+
+    /// Sends the collected fees to `destination`. Remaining accounts are
+    /// [treasury_ta, destination_ta] pairs of the token fees to sweep.
+    pub fn sweep_fees<'info>(ctx: Context<'_, '_, '_, 'info, SweepFees<'info>>) -> Result<()> {
+        if ctx.remaining_accounts.len() % 2 != 0 {
+            return Err(error!(ErrorCode::WrongRemainingAccountsSize));
+        }
+        let accounts = &ctx.accounts;
+        let namespace_seed = accounts.config.namespace_seed();
+        let (_, treasury_bump) = treasury(&namespace_seed);
+        let treasury_seeds: &[&[u8]] = &[b"treasury".as_ref(), &namespace_seed, &[treasury_bump]];
+        for pair in ctx.remaining_accounts.chunks(2) {
+            let amount = get_token_account(&pair[0])?.amount;
+            anchor_spl::token::transfer(
+                CpiContext::new_with_signer(
+                    accounts.token_program.to_account_info(),
+                    anchor_spl::token::Transfer {
+                        from: pair[0].clone(),
+                        to: pair[1].clone(),
+                        authority: accounts.treasury.to_account_info(),
+                    },
+                    &[treasury_seeds],
+                ),
+                amount,
+            )?;
+        }
+
+        // The treasury stays rent exempt so that the next fee can land.
+        let lamports = accounts
+            .treasury
+            .lamports()
+            .saturating_sub(Rent::get()?.minimum_balance(0));
+        if lamports > 0 {
+            anchor_lang::system_program::transfer(
+                CpiContext::new_with_signer(
+                    accounts.system_program.to_account_info(),
+                    anchor_lang::system_program::Transfer {
+                        from: accounts.treasury.to_account_info(),
+                        to: accounts.destination.to_account_info(),
+                    },
+                    &[treasury_seeds],
+                ),
+                lamports,
+            )?;
+        }
+        emit!(FeeSweepEvent {
+            destination: accounts.destination.key(),
+            lamports,
+        });
+        Ok(())
+    }
+
+    /// Moves legacy `[mint, owner]` vaults into the pools and credits their
+    /// owner's locker. Remaining accounts are [mint, locker, owner, vault_ta,
+    /// burn_ta, balance] chunks; vaults are closed with their rent refunded to
+    /// the owner.
+    pub fn migrate_legacy_vaults<'info>(
+        ctx: Context<'_, '_, '_, 'info, MigrateLegacyVaults<'info>>,
+        vault_bumps: Vec<u8>,
+        burn_bumps: Vec<u8>,
+    ) -> Result<()> {
+        const CHUNK_SIZE: usize = 6;
+        let remaining_accounts = ctx.remaining_accounts;
+        if remaining_accounts.len() != vault_bumps.len() * CHUNK_SIZE
+            || vault_bumps.len() != burn_bumps.len()
+        {
+            return Err(error!(ErrorCode::WrongRemainingAccountsSize));
+        }
+        let accounts = &ctx.accounts;
+        for (index, chunk) in remaining_accounts.chunks(CHUNK_SIZE).enumerate() {
+            let (mint, locker, owner) = (&chunk[0], &chunk[1], &chunk[2]);
+            let (vault_ta, burn_ta, balance) = (&chunk[3], &chunk[4], &chunk[5]);
+            if *locker.owner != crate::ID || !accounts.config.has_locker(locker, owner.key) {
+                return Err(error!(ErrorCode::InvalidLocker));
+            }
+            let vault_address = Pubkey::create_program_address(
+                &[mint.key.as_ref(), owner.key.as_ref(), &[vault_bumps[index]]],
+                &crate::ID,
+            )
+            .map_err(|_| error!(ErrorCode::InvalidVault))?;
+            if vault_address != vault_ta.key() || get_token_account(vault_ta)?.mint != mint.key() {
+                return Err(error!(ErrorCode::InvalidVault));
+            }
+            init_pool_ta(
+                burn_ta,
+                mint,
+                &accounts.config.namespace_seed(),
+                burn_bumps[index],
+                &accounts.admin,
+                &accounts.system_program,
+                &accounts.token_program,
+                &accounts.rent.to_account_info(),
+            )?;
+
+            let amount = drain_legacy_vault(
+                mint,
+                vault_ta,
+                burn_ta,
+                owner.key,
+                vault_bumps[index],
+                owner,
+                &accounts.token_program,
+            )?;
+            credit_deposit(
+                balance,
+                locker.key,
+                mint.key,
+                amount,
+                None,
+                &accounts.admin,
+                &accounts.system_program,
+            )?;
+            emit!(LegacyVaultMigrationEvent {
+                locker: locker.key(),
+                mint: mint.key(),
+                amount,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn set_rent_recipient(ctx: Context<UpdateConfig>, rent_recipient: Pubkey) -> Result<()> {
+        ctx.accounts.config.rent_recipient = rent_recipient;
+        Ok(())
+    }
+
+    /// Closes empty pools and legacy vaults. Remaining accounts are
+    /// [mint, token_account] pairs; a `vault_owners` entry of None is the
+    /// `[mint, namespace]` pool, otherwise the `[mint, owner]` legacy vault.
+    pub fn reclaim_rent<'info>(
+        ctx: Context<'_, '_, '_, 'info, ReclaimRent<'info>>,
+        bumps: Vec<u8>,
+        vault_owners: Vec<Option<Pubkey>>,
+    ) -> Result<()> {
+        let accounts = &ctx.accounts;
+        if accounts.config.rent_recipient == Pubkey::default() {
+            return Err(error!(ErrorCode::RentRecipientNotSet));
+        }
+        if ctx.remaining_accounts.len() != bumps.len() * 2 || bumps.len() != vault_owners.len() {
+            return Err(error!(ErrorCode::WrongRemainingAccountsSize));
+        }
+        let namespace_seed = accounts.config.namespace_seed();
+        let mut lamports: u64 = 0;
+        for (index, pair) in ctx.remaining_accounts.chunks(2).enumerate() {
+            let (mint, token_account) = (&pair[0], &pair[1]);
+            let seed = match vault_owners[index] {
+                // Legacy vaults predate namespaces.
+                Some(_) if accounts.config.namespace != 0 => {
+                    return Err(error!(ErrorCode::InvalidVault));
+                }
+                Some(owner) => owner.to_bytes().to_vec(),
+                None => namespace_seed.clone(),
+            };
+            let signer_seeds: &[&[u8]] = &[mint.key.as_ref(), &seed, &[bumps[index]]];
+            let address = Pubkey::create_program_address(signer_seeds, &crate::ID)
+                .map_err(|_| error!(ErrorCode::InvalidVault))?;
+            let token_account_data = get_token_account(token_account)?;
+            if address != token_account.key()
+                || token_account_data.mint != mint.key()
+                || token_account_data.amount != 0
+            {
+                return Err(error!(ErrorCode::InvalidVault));
+            }
+            lamports += token_account.lamports();
+            anchor_spl::token::close_account(CpiContext::new_with_signer(
+                accounts.token_program.to_account_info(),
+                anchor_spl::token::CloseAccount {
+                    account: token_account.clone(),
+                    destination: accounts.rent_recipient.to_account_info(),
+                    authority: token_account.clone(),
+                },
+                &[signer_seeds],
+            ))?;
+        }
+        emit!(RentReclaimEvent {
+            rent_recipient: accounts.rent_recipient.key(),
+            closed: bumps.len() as u32,
+            lamports,
+        });
+        Ok(())
+    }
+
+    pub fn set_locker_daily_cap(ctx: Context<UpdateConfig>, cap: u64) -> Result<()> {
+        ctx.accounts.config.locker_daily_cap = cap;
+        Ok(())
+    }
+
+    pub fn set_mint_locker_daily_cap(ctx: Context<UpdateMintPolicy>, cap: u64) -> Result<()> {
+        ctx.accounts.mint_policy.locker_daily_cap = cap;
+        Ok(())
+    }
+
+    /// Forces withdrawals of this mint through `request_withdraw` when non-zero.
+    pub fn set_withdraw_delay(ctx: Context<UpdateMintPolicy>, delay: i64) -> Result<()> {
+        if delay < 0 {
+            return Err(error!(ErrorCode::InvalidBeforeState));
+        }
+        ctx.accounts.mint_policy.withdraw_delay = delay;
+        Ok(())
+    }
+
+    /// Per-mint withdrawal fees, `withdraw_fee` in lamports overrides the config
+    /// fee when non-zero and `withdraw_fee_bps` is kept from fungible amounts.
+    pub fn set_withdraw_fee(
+        ctx: Context<UpdateMintPolicy>,
+        withdraw_fee: u64,
+        withdraw_fee_bps: u16,
+    ) -> Result<()> {
+        if withdraw_fee_bps > MintPolicy::MAX_FEE_BPS {
+            return Err(error!(ErrorCode::InvalidFee));
+        }
+        let mint_policy = &mut ctx.accounts.mint_policy;
+        mint_policy.withdraw_fee = withdraw_fee;
+        mint_policy.withdraw_fee_bps = withdraw_fee_bps;
+        Ok(())
+    }
+
+    /// Only enforced for mints whose authority is the program mint authority.
+    pub fn set_supply_cap(ctx: Context<UpdateMintPolicy>, supply_cap: u64) -> Result<()> {
+        ctx.accounts.mint_policy.supply_cap = supply_cap;
+        Ok(())
+    }
+
+    /// Raises the daily cap of every mint of a locker, e.g. for VIP players.
+    pub fn raise_locker_daily_cap(ctx: Context<RaiseLockerDailyCap>, cap: u64) -> Result<()> {
+        LockerCap { daily_cap: cap }.store(&ctx.accounts.locker)
+    }
+
+    /// Lockers of a namespace other than 0 are at `[owner, namespace]`.
+    pub fn init_namespace_locker(ctx: Context<InitNamespaceLocker>) -> Result<()> {
+        ctx.accounts.locker.owner = ctx.accounts.owner.key();
+        Ok(())
+    }
+
+    /// Additional lockers of an owner, e.g. to keep a tournament inventory apart.
+    /// Deposits and withdrawals accept them like the v2 locker.
+    pub fn init_locker_v3(ctx: Context<InitLockerV3>, index: u16) -> Result<()> {
+        let locker = &mut ctx.accounts.locker;
+        locker.owner = ctx.accounts.owner.key();
+        locker.version = Locker::V3;
+        LockerV3 { index }.store(&locker.to_account_info())
+    }
+
     pub fn init_locker_v2(ctx: Context<InitLockerV2>) -> Result<()> {
         ctx.accounts.locker.owner = ctx.accounts.owner.key();
-        ctx.accounts.locker.space = 0 as u64;
+        ctx.accounts.locker.space = 0;
         Ok(())
     }
 
@@ -34,161 +371,1324 @@ pub mod casier {
         burn_bumps: Vec<u8>,
         pnft_count: u8,
         nonce: u64,
+        valid_until: Option<ValidUntil>,
+        lock_until: Option<i64>,
     ) -> Result<()> {
-        const PNFT_CHUNK_SIZE: u8 = 8;
-        const NORMAL_CHUNK_SIZE: u8 = 4;
-        let pnft_ra_length = pnft_count * PNFT_CHUNK_SIZE + (if pnft_count > 0 { 3 } else { 0 });
-        if ((ctx.remaining_accounts.len() as u8) - pnft_ra_length) % NORMAL_CHUNK_SIZE != 0 {
+        check_valid_until(valid_until)?;
+        let accounts: &'b mut DepositBatch<'info> = ctx.accounts;
+        check_cpi_caller(&accounts.config, &accounts.instructions)?;
+        if accounts.locker.space != nonce {
+            return Err(error!(ErrorCode::InvalidBeforeState));
+        }
+        accounts.locker.space += 1;
+        if let Some(delegate) = accounts.delegate.as_deref() {
+            delegate.check(Delegate::DEPOSIT)?;
+            // Only the owner commits their items for a season.
+            if lock_until.is_some() {
+                return Err(error!(ErrorCode::Unauthorized));
+            }
+        }
+
+        perform_deposit_batch(
+            &mut accounts.config,
+            &mut accounts.locker,
+            &accounts.owner,
+            &accounts.locker_owner,
+            &accounts.admin,
+            &accounts.system_program,
+            &accounts.token_program,
+            &accounts.rent,
+            &accounts.treasury,
+            ctx.remaining_accounts,
+            deposit_amounts,
+            vault_bumps,
+            burn_bumps,
+            pnft_count,
+            lock_until,
+            accounts.delegate.as_deref_mut(),
+        )
+    }
+
+    pub fn withdraw_v2_batch<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, WithdrawV2Batch<'info>>,
+        withdraw_amounts: Vec<u64>,
+        vault_bumps: Vec<u8>,
+        burn_bumps: Vec<u8>,
+        pnft_count: u8,
+        nonce: u64,
+        valid_until: Option<ValidUntil>,
+    ) -> Result<()> {
+        check_valid_until(valid_until)?;
+        let accounts: &'b mut WithdrawV2Batch<'info> = ctx.accounts;
+        check_cpi_caller(&accounts.config, &accounts.instructions)?;
+        if accounts.locker.owner != accounts.user_ta_owner.key() && accounts.delegate.is_none() {
+            return Err(error!(ErrorCode::Unauthorized));
+        }
+        if accounts.locker.space != nonce {
+            return Err(error!(ErrorCode::InvalidBeforeState));
+        }
+        accounts.locker.space += 1;
+
+        perform_withdraw_batch(
+            accounts,
+            ctx.remaining_accounts,
+            withdraw_amounts,
+            vault_bumps,
+            burn_bumps,
+            pnft_count,
+            false,
+        )
+    }
+
+    pub fn init_guild(
+        ctx: Context<InitGuild>,
+        id: u64,
+        members: Vec<Pubkey>,
+        threshold: u8,
+    ) -> Result<()> {
+        let guild = &mut ctx.accounts.guild;
+        guild.id = id;
+        guild.members = members;
+        guild.threshold = threshold;
+        guild.check_members()?;
+        ctx.accounts.locker.owner = guild.key();
+        Ok(())
+    }
+
+    /// Remaining accounts are the approving members.
+    pub fn add_guild_member(ctx: Context<UpdateGuild>, member: Pubkey) -> Result<()> {
+        let guild = &mut ctx.accounts.guild;
+        guild.check_approvals(ctx.remaining_accounts)?;
+        guild.members.push(member);
+        guild.check_members()
+    }
+
+    /// Remaining accounts are the approving members.
+    pub fn remove_guild_member(ctx: Context<UpdateGuild>, member: Pubkey) -> Result<()> {
+        let guild = &mut ctx.accounts.guild;
+        guild.check_approvals(ctx.remaining_accounts)?;
+        guild.members.retain(|m| *m != member);
+        guild.check_members()
+    }
+
+    /// Same as `deposit_batch` with any guild member depositing.
+    pub fn guild_deposit_batch<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, GuildDepositBatch<'info>>,
+        deposit_amounts: Vec<u64>,
+        vault_bumps: Vec<u8>,
+        burn_bumps: Vec<u8>,
+        pnft_count: u8,
+        nonce: u64,
+        valid_until: Option<ValidUntil>,
+    ) -> Result<()> {
+        check_valid_until(valid_until)?;
+        let accounts: &'b mut GuildDepositBatch<'info> = ctx.accounts;
+        check_cpi_caller(&accounts.config, &accounts.instructions)?;
+        if accounts.locker.space != nonce {
+            return Err(error!(ErrorCode::InvalidBeforeState));
+        }
+        accounts.locker.space += 1;
+
+        perform_deposit_batch(
+            &mut accounts.config,
+            &mut accounts.locker,
+            &accounts.depositor,
+            accounts.guild.as_ref(),
+            &accounts.admin,
+            &accounts.system_program,
+            &accounts.token_program,
+            &accounts.rent,
+            &accounts.treasury,
+            ctx.remaining_accounts,
+            deposit_amounts,
+            vault_bumps,
+            burn_bumps,
+            pnft_count,
+            None,
+            None,
+        )
+    }
+
+    /// Same as `withdraw_v2_batch` to a guild member, with the first
+    /// `approver_count` remaining accounts being the approving members.
+    pub fn guild_withdraw_batch<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, GuildWithdrawBatch<'info>>,
+        withdraw_amounts: Vec<u64>,
+        vault_bumps: Vec<u8>,
+        burn_bumps: Vec<u8>,
+        pnft_count: u8,
+        approver_count: u8,
+        nonce: u64,
+        valid_until: Option<ValidUntil>,
+    ) -> Result<()> {
+        check_valid_until(valid_until)?;
+        let remaining_accounts: &'c [AccountInfo<'info>] = ctx.remaining_accounts;
+        if remaining_accounts.len() < approver_count as usize {
             return Err(error!(ErrorCode::WrongRemainingAccountsSize));
         }
-        let accounts: &'b mut DepositBatch<'info> = ctx.accounts;
-        let config: &'b mut Account<'info, Config> = &mut accounts.config;
-        let locker: &'b mut Account<'info, Locker> = &mut accounts.locker;
+        let (approvers, remaining_accounts) = remaining_accounts.split_at(approver_count as usize);
+        ctx.accounts.guild.check_approvals(approvers)?;
 
-        if locker.space != nonce {
+        let accounts: &'b mut WithdrawV2Batch<'info> = &mut ctx.accounts.withdraw;
+        check_cpi_caller(&accounts.config, &accounts.instructions)?;
+        if accounts.locker.space != nonce {
             return Err(error!(ErrorCode::InvalidBeforeState));
         }
-        locker.space += 1;
+        accounts.locker.space += 1;
 
-        let owner: &'b Signer<'info> = &accounts.owner;
-        let admin: &'b Signer<'info> = &accounts.admin;
-        let system_program: &'b Program<'info, System> = &accounts.system_program;
-        let token_program: &'b Program<'info, Token> = &accounts.token_program;
-        let rent: &'b Sysvar<'info, Rent> = &accounts.rent;
+        perform_withdraw_batch(
+            accounts,
+            remaining_accounts,
+            withdraw_amounts,
+            vault_bumps,
+            burn_bumps,
+            pnft_count,
+            true,
+        )
+    }
+
+    /// Burns consumed items from the pool, signed by the admin. Remaining
+    /// accounts are chunks of [mint, burn_ta, balance], NFTs first as [mint,
+    /// burn_ta, balance, metadata, edition, token_record] behind
+    /// [token_metadata_program, instructions]. Pass the token metadata program
+    /// as the token record of non programmable NFTs.
+    pub fn burn_batch<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, BurnBatch<'info>>,
+        burn_amounts: Vec<u64>,
+        burn_bumps: Vec<u8>,
+        nft_count: u8,
+    ) -> Result<()> {
+        const NFT_CHUNK_SIZE: usize = 6;
+        const NORMAL_CHUNK_SIZE: usize = 3;
         let remaining_accounts: &'c [AccountInfo<'info>] = ctx.remaining_accounts;
+        let nft_ra_length =
+            nft_count as usize * NFT_CHUNK_SIZE + (if nft_count > 0 { 2 } else { 0 });
+        if burn_amounts.is_empty()
+            || burn_bumps.len() != burn_amounts.len()
+            || remaining_accounts.len() < nft_ra_length
+            || (remaining_accounts.len() - nft_ra_length) % NORMAL_CHUNK_SIZE != 0
+            || burn_amounts.len()
+                != nft_count as usize
+                    + (remaining_accounts.len() - nft_ra_length) / NORMAL_CHUNK_SIZE
+        {
+            return Err(error!(ErrorCode::WrongRemainingAccountsSize));
+        }
+        let accounts = ctx.accounts;
         let token_metadata_program = &remaining_accounts[0];
-        let spl_ata_program_info = &remaining_accounts[1];
-        let instructions = &remaining_accounts[2];
-        let mut index = if pnft_count > 0 { 3 } else { 0 };
-        let mut mint_index: usize = 0;
-        while index < remaining_accounts.len() {
-            let mut pd = PerformDepositV2 {
-                config: config,
-                locker: locker,
+        let instructions = &remaining_accounts[1];
+        let mut index = if nft_count > 0 { 2 } else { 0 };
+        for (mint_index, burn_amount) in burn_amounts.into_iter().enumerate() {
+            let mut pd = PerformBurn {
+                config: &accounts.config,
+                locker: &accounts.locker,
                 mint: &remaining_accounts[index],
-                owner: owner,
-                admin: admin,
-                user_ta: &remaining_accounts[index + 1],
-                vault_ta: &remaining_accounts[index + 2],
-                burn_ta: &remaining_accounts[index + 3],
+                burn_ta: &remaining_accounts[index + 1],
+                balance: &remaining_accounts[index + 2],
+                admin: &accounts.admin,
+                co_signer: accounts.owner.as_ref().map(|owner| owner.key()),
                 metadata: None,
-                token_record: None,
-                destination_token_record: None,
                 edition: None,
-                token_metadata_program: token_metadata_program,
-                instructions: instructions,
-                spl_ata_program_info: spl_ata_program_info,
-                system_program: system_program,
-                token_program: token_program,
-                rent: rent,
+                token_record: None,
+                token_metadata_program,
+                instructions,
+                system_program: &accounts.system_program,
+                token_program: &accounts.token_program,
             };
-            if (index as u8) < pnft_ra_length {
-                pd.metadata = Some(&remaining_accounts[index + 4]);
-                pd.token_record = Some(&remaining_accounts[index + 5]);
-                pd.destination_token_record = Some(&remaining_accounts[index + 6]);
-                pd.edition = Some(&remaining_accounts[index + 7]);
-            }
-            perform_deposit_v2(
-                pd,
-                vault_bumps[mint_index],
-                deposit_amounts[mint_index],
-                burn_bumps[mint_index],
-            )?;
-            index += if pnft_ra_length == 0 || (index as u8) > pnft_ra_length {
-                NORMAL_CHUNK_SIZE as usize
+            if index < nft_ra_length {
+                pd.metadata = Some(&remaining_accounts[index + 3]);
+                pd.edition = Some(&remaining_accounts[index + 4]);
+                let token_record = &remaining_accounts[index + 5];
+                if token_record.key() != mpl_token_metadata::ID {
+                    pd.token_record = Some(token_record);
+                }
+                index += NFT_CHUNK_SIZE;
             } else {
-                PNFT_CHUNK_SIZE as usize
-            };
-            mint_index += 1;
+                index += NORMAL_CHUNK_SIZE;
+            }
+            perform_burn(pd, burn_amount, burn_bumps[mint_index])?;
         }
         Ok(())
     }
 
-    pub fn withdraw_v2_batch<'a, 'b, 'c, 'info>(
-        ctx: Context<'a, 'b, 'c, 'info, WithdrawV2Batch<'info>>,
-        withdraw_amounts: Vec<u64>,
-        vault_bumps: Vec<u8>,
-        burn_bumps: Vec<u8>,
-        pnft_count: u8,
+    /// Lets `delegate` deposit and/or withdraw for the locker without the owner
+    /// signing, e.g. a game client session key.
+    pub fn create_delegate(
+        ctx: Context<CreateDelegate>,
+        delegate: Pubkey,
+        operations: u8,
+        allowances: Vec<TradeItem>,
+        expiry: i64,
+    ) -> Result<()> {
+        if allowances.len() > Delegate::MAX_ALLOWANCES {
+            return Err(error!(ErrorCode::InvalidBeforeState));
+        }
+        let delegate_account = &mut ctx.accounts.delegate_account;
+        delegate_account.locker = ctx.accounts.locker.key();
+        delegate_account.delegate = delegate;
+        delegate_account.operations = operations;
+        delegate_account.allowances = allowances;
+        delegate_account.expiry = expiry;
+        Ok(())
+    }
+
+    pub fn revoke_delegate(_ctx: Context<RevokeDelegate>) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn set_recovery(ctx: Context<SetRecovery>, recovery_key: Pubkey, delay: i64) -> Result<()> {
+        if delay < 0 {
+            return Err(error!(ErrorCode::InvalidBeforeState));
+        }
+        let recovery = &mut ctx.accounts.recovery;
+        recovery.locker = ctx.accounts.locker.key();
+        recovery.recovery_key = recovery_key;
+        recovery.delay = delay;
+        Ok(())
+    }
+
+    pub fn remove_recovery(ctx: Context<UpdateRecovery>) -> Result<()> {
+        ctx.accounts
+            .recovery
+            .close(ctx.accounts.owner.to_account_info())
+    }
+
+    pub fn initiate_recovery(ctx: Context<InitiateRecovery>, new_owner: Pubkey) -> Result<()> {
+        let recovery = &mut ctx.accounts.recovery;
+        recovery.new_owner = new_owner;
+        recovery.available_at = Clock::get()?.unix_timestamp + recovery.delay;
+        emit!(RecoveryInitiatedEvent {
+            locker: recovery.locker,
+            new_owner,
+            available_at: recovery.available_at,
+        });
+        Ok(())
+    }
+
+    pub fn cancel_recovery(ctx: Context<UpdateRecovery>) -> Result<()> {
+        let recovery = &mut ctx.accounts.recovery;
+        emit!(RecoveryCancelledEvent {
+            locker: recovery.locker,
+            new_owner: recovery.new_owner,
+        });
+        recovery.new_owner = Pubkey::default();
+        recovery.available_at = 0;
+        Ok(())
+    }
+
+    /// Moves ledger entries of the locker, locks included, to the locker of the
+    /// new owner, in as many batches as needed before `finalize_recovery`.
+    /// Entries holding rented or lent out items are refused, settle their
+    /// rentals first. Remaining accounts are chunks of [mint, balance,
+    /// new_balance, rental], `rental` being the locker's `[b"rental", locker,
+    /// mint]` as lender.
+    pub fn recover_locker<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, RecoverLocker<'info>>,
+    ) -> Result<()> {
+        const CHUNK_SIZE: usize = 4;
+        let remaining_accounts: &'c [AccountInfo<'info>] = ctx.remaining_accounts;
+        if remaining_accounts.len() % CHUNK_SIZE != 0 {
+            return Err(error!(ErrorCode::WrongRemainingAccountsSize));
+        }
+        let accounts = ctx.accounts;
+        let recovery = &accounts.recovery;
+        if recovery.new_owner == Pubkey::default()
+            || Clock::get()?.unix_timestamp < recovery.available_at
+        {
+            return Err(error!(ErrorCode::RecoveryNotReady));
+        }
+
+        let locker = accounts.locker.key();
+        let new_locker = accounts.new_locker.key();
+        let payer = accounts.recovery_key.to_account_info();
+        let system_program = accounts.system_program.to_account_info();
+        for chunk in remaining_accounts.chunks(CHUNK_SIZE) {
+            let mint = chunk[0].key;
+            let mut balance =
+                load_or_init_balance(&chunk[1], &locker, mint, &payer, &system_program)?;
+            if balance.rented > 0 {
+                return Err(error!(ErrorCode::RentedBalance));
+            }
+            // Lent out items settle back to this locker, so they must come home
+            // before it is abandoned.
+            let (rental, _) = Pubkey::find_program_address(
+                &[b"rental".as_ref(), locker.as_ref(), mint.as_ref()],
+                &crate::ID,
+            );
+            if chunk[3].key() != rental {
+                return Err(error!(ErrorCode::InvalidRental));
+            }
+            if *chunk[3].owner == crate::ID {
+                return Err(error!(ErrorCode::LentBalance));
+            }
+            let mut new_balance =
+                load_or_init_balance(&chunk[2], &new_locker, mint, &payer, &system_program)?;
+            let amount = balance.amount;
+            balance.debit(amount)?;
+            new_balance.credit(amount)?;
+            new_balance.lock_until = new_balance.lock_until.max(balance.lock_until);
+            store_balance(&chunk[1], &balance)?;
+            store_balance(&chunk[2], &new_balance)?;
+            emit!(LockerTransferEvent {
+                from_locker: locker,
+                to_locker: new_locker,
+                mint: *mint,
+                amount,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Ends a recovery once every entry moved and closes it.
+    pub fn finalize_recovery(ctx: Context<FinalizeRecovery>) -> Result<()> {
+        let accounts = ctx.accounts;
+        let recovery = &accounts.recovery;
+        if recovery.new_owner == Pubkey::default()
+            || Clock::get()?.unix_timestamp < recovery.available_at
+        {
+            return Err(error!(ErrorCode::RecoveryNotReady));
+        }
+
+        // Operations signed for either locker can no longer be replayed.
+        let nonce = accounts.locker.space.max(accounts.new_locker.space) + 1;
+        accounts.locker.space = nonce;
+        accounts.new_locker.space = nonce;
+        emit!(LockerRecoveredEvent {
+            locker: accounts.locker.key(),
+            new_locker: accounts.new_locker.key(),
+        });
+        Ok(())
+    }
+
+    /// Lends `amount` of a mint to another locker until `expiry`. The borrower
+    /// must not already hold the mint, as its whole entry stays locked.
+    pub fn create_rental(ctx: Context<CreateRental>, amount: u64, expiry: i64) -> Result<()> {
+        let accounts = ctx.accounts;
+        if expiry <= Clock::get()?.unix_timestamp {
+            return Err(error!(ErrorCode::OperationExpired));
+        }
+        let lender_locker = accounts.lender_locker.key();
+        let borrower_locker = accounts.borrower_locker.key();
+        let mint = accounts.mint.key();
+        accounts.lender_balance.check_unlocked(&Clock::get()?)?;
+        accounts.lender_balance.debit(amount)?;
+        let mut borrower_balance = load_or_init_balance(
+            &accounts.borrower_balance,
+            &borrower_locker,
+            &mint,
+            &accounts.lender_owner,
+            &accounts.system_program,
+        )?;
+        if borrower_balance.amount > 0 {
+            return Err(error!(ErrorCode::RentalBalanceInUse));
+        }
+        let borrower_lock_until = borrower_balance.lock_until;
+        borrower_balance.credit(amount)?;
+        borrower_balance.rented = amount;
+        borrower_balance.lock_until = i64::MAX;
+        store_balance(&accounts.borrower_balance, &borrower_balance)?;
+
+        let rental = &mut accounts.rental;
+        rental.lender_locker = lender_locker;
+        rental.borrower_locker = borrower_locker;
+        rental.mint = mint;
+        rental.amount = amount;
+        rental.expiry = expiry;
+        rental.borrower_lock_until = borrower_lock_until;
+        emit!(RentalEvent {
+            lender_locker,
+            borrower_locker,
+            mint,
+            amount,
+            expiry,
+            settled: false,
+        });
+        Ok(())
+    }
+
+    /// Returns rented items to the lender once expired, callable by anyone.
+    pub fn settle_rental(ctx: Context<SettleRental>) -> Result<()> {
+        let accounts = ctx.accounts;
+        let rental = &accounts.rental;
+        if Clock::get()?.unix_timestamp < rental.expiry {
+            return Err(error!(ErrorCode::RentalNotExpired));
+        }
+        accounts.borrower_balance.debit(rental.amount)?;
+        accounts.borrower_balance.rented = 0;
+        accounts.borrower_balance.lock_until = rental.borrower_lock_until;
+        accounts.lender_balance.credit(rental.amount)?;
+        emit!(RentalEvent {
+            lender_locker: rental.lender_locker,
+            borrower_locker: rental.borrower_locker,
+            mint: rental.mint,
+            amount: rental.amount,
+            expiry: rental.expiry,
+            settled: true,
+        });
+        Ok(())
+    }
+
+    /// Read-only: returns the locker's ledger entries for `mints` as return
+    /// data. Remaining accounts are the `[b"balance", locker, mint]` entries in
+    /// the same order; entries never created read as empty.
+    pub fn get_balances(ctx: Context<GetBalances>, mints: Vec<Pubkey>) -> Result<Vec<BalanceView>> {
+        if mints.len() > BalanceView::MAX_PER_CALL {
+            return Err(error!(ErrorCode::TooManyBalances));
+        }
+        if ctx.remaining_accounts.len() != mints.len() {
+            return Err(error!(ErrorCode::WrongRemainingAccountsSize));
+        }
+        let locker = ctx.accounts.locker.key();
+        let mut views = Vec::with_capacity(mints.len());
+        for (mint, balance_ai) in mints.iter().zip(ctx.remaining_accounts.iter()) {
+            let (address, _) = Pubkey::find_program_address(
+                &[b"balance".as_ref(), locker.as_ref(), mint.as_ref()],
+                &crate::ID,
+            );
+            if address != balance_ai.key() {
+                return Err(error!(ErrorCode::InvalidBalanceAccount));
+            }
+            let mut view = BalanceView {
+                mint: *mint,
+                ..Default::default()
+            };
+            if *balance_ai.owner == crate::ID {
+                let balance = Balance::try_deserialize(&mut &balance_ai.try_borrow_data()?[..])?;
+                view.amount = balance.amount;
+                view.lock_until = balance.lock_until;
+            }
+            views.push(view);
+        }
+        Ok(views)
+    }
+
+    pub fn redeem_voucher<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, RedeemVoucher<'info>>,
+        amount: u64,
+        vault_bump: u8,
+        burn_bump: u8,
         nonce: u64,
+        expiry: i64,
     ) -> Result<()> {
-        const PNFT_CHUNK_SIZE: u8 = 9;
-        const NORMAL_CHUNK_SIZE: u8 = 5;
-        let pnft_ra_length = pnft_count * PNFT_CHUNK_SIZE + (if pnft_count > 0 { 2 } else { 0 });
-        if ((ctx.remaining_accounts.len() as u8) - pnft_ra_length) % NORMAL_CHUNK_SIZE != 0 {
+        const PNFT_CHUNK_SIZE: usize = 12;
+        const NORMAL_CHUNK_SIZE: usize = 8;
+        let remaining_accounts: &'c [AccountInfo<'info>] = ctx.remaining_accounts;
+        if remaining_accounts.len() != 2 + NORMAL_CHUNK_SIZE
+            && remaining_accounts.len() != 2 + PNFT_CHUNK_SIZE
+        {
             return Err(error!(ErrorCode::WrongRemainingAccountsSize));
         }
-        let accounts: &'b mut WithdrawV2Batch<'info> = ctx.accounts;
+        check_valid_until(Some(ValidUntil::Timestamp(expiry)))?;
+
+        let accounts: &'b mut RedeemVoucher<'info> = ctx.accounts;
+        check_cpi_caller(&accounts.config, &remaining_accounts[1])?;
         let config: &'b mut Account<'info, Config> = &mut accounts.config;
         let locker: &'b mut Account<'info, Locker> = &mut accounts.locker;
-
         if locker.space != nonce {
             return Err(error!(ErrorCode::InvalidBeforeState));
         }
 
-        locker.space += 1;
-        let admin: &'b Signer<'info> = &accounts.admin;
-        let user_ta_owner: &'b Signer<'info> = &accounts.user_ta_owner;
-        let system_program: &'b Program<'info, System> = &accounts.system_program;
-        let token_program: &'b Program<'info, Token> = &accounts.token_program;
-        let associated_token_program: &'b Program<'info, AssociatedToken> =
-            &accounts.associated_token_program;
-        let rent: &'b Sysvar<'info, Rent> = &accounts.rent;
-        let remaining_accounts: &'c [AccountInfo<'info>] = ctx.remaining_accounts;
         let token_metadata_program = &remaining_accounts[0];
         let instructions = &remaining_accounts[1];
-        let mut index = if pnft_count > 0 { 2 } else { 0 };
-        let mut mint_index = 0;
-        while index < remaining_accounts.len() {
-            let mut pd = PerformWithdrawV2 {
-                config: config,
-                locker: locker,
-                mint: &remaining_accounts[index],
-                admin: admin,
-                user_ta_owner: user_ta_owner,
-                user_ta: &remaining_accounts[index + 1],
-                vault_ta: &remaining_accounts[index + 2],
-                vault_ta_owner: &remaining_accounts[index + 3],
-                burn_ta: &remaining_accounts[index + 4],
-                metadata: None,
-                token_record: None,
-                destination_token_record: None,
-                edition: None,
-                token_metadata_program: token_metadata_program,
-                instructions: instructions,
-                system_program: system_program,
-                token_program: token_program,
-                associated_token_program: associated_token_program,
-                rent: rent,
-            };
-            if (index as u8) < pnft_ra_length {
-                pd.metadata = Some(&remaining_accounts[index + 5]);
-                pd.token_record = Some(&remaining_accounts[index + 6]);
-                pd.destination_token_record = Some(&remaining_accounts[index + 7]);
-                pd.edition = Some(&remaining_accounts[index + 8]);
-            }
-            perform_withdraw_v2(
-                pd,
-                withdraw_amounts[mint_index],
-                vault_bumps[mint_index],
-                burn_bumps[mint_index],
+        let mint = &remaining_accounts[2];
+        let voucher = Voucher {
+            locker: locker.key(),
+            mint: mint.key(),
+            amount,
+            nonce,
+            expiry,
+        };
+        verify_ed25519_instruction(instructions, &config.admin, &voucher.message())?;
+        if remaining_accounts[5].key() != locker.owner {
+            return Err(error!(ErrorCode::InvalidVault));
+        }
+        locker.space += 1;
+
+        let mut pd = PerformWithdrawV2 {
+            config,
+            locker,
+            mint,
+            user_ta_owner: &accounts.owner,
+            recipient: accounts.owner.as_ref(),
+            user_ta: &remaining_accounts[3],
+            vault_ta: &remaining_accounts[4],
+            vault_ta_owner: &remaining_accounts[5],
+            burn_ta: &remaining_accounts[6],
+            balance: Some(&remaining_accounts[7]),
+            mint_policy: &remaining_accounts[8],
+            delayed: false,
+            treasury: &accounts.treasury,
+            treasury_ta: &remaining_accounts[9],
+            metadata: None,
+            token_record: None,
+            destination_token_record: None,
+            edition: None,
+            token_metadata_program,
+            instructions,
+            system_program: &accounts.system_program,
+            token_program: &accounts.token_program,
+            associated_token_program: &accounts.associated_token_program,
+            rent: &accounts.rent,
+        };
+        if remaining_accounts.len() == 2 + PNFT_CHUNK_SIZE {
+            pd.metadata = Some(&remaining_accounts[10]);
+            pd.token_record = Some(&remaining_accounts[11]);
+            pd.destination_token_record = Some(&remaining_accounts[12]);
+            pd.edition = Some(&remaining_accounts[13]);
+        }
+        perform_withdraw_v2(pd, amount, vault_bump, burn_bump)
+    }
+
+    pub fn transfer_between_lockers<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, TransferBetweenLockers<'info>>,
+        amounts: Vec<u64>,
+    ) -> Result<()> {
+        const CHUNK_SIZE: usize = 3;
+        let remaining_accounts: &'c [AccountInfo<'info>] = ctx.remaining_accounts;
+        if remaining_accounts.len() != amounts.len() * CHUNK_SIZE {
+            return Err(error!(ErrorCode::WrongRemainingAccountsSize));
+        }
+        let accounts = ctx.accounts;
+        let owners_signed = accounts.from_owner.is_signer && accounts.to_owner.is_signer;
+        if !owners_signed && !accounts.admin.is_signer {
+            return Err(error!(ErrorCode::Unauthorized));
+        }
+
+        let from_locker = accounts.from_locker.key();
+        let to_locker = accounts.to_locker.key();
+        let payer = accounts.payer.to_account_info();
+        let system_program = accounts.system_program.to_account_info();
+        for (chunk, amount) in remaining_accounts.chunks(CHUNK_SIZE).zip(amounts) {
+            move_balance(
+                &chunk[1],
+                &chunk[2],
+                &from_locker,
+                &to_locker,
+                chunk[0].key,
+                amount,
+                &payer,
+                &system_program,
             )?;
+        }
+        Ok(())
+    }
 
-            index += if pnft_ra_length == 0 || (index as u8) > pnft_ra_length {
-                NORMAL_CHUNK_SIZE as usize
-            } else {
-                PNFT_CHUNK_SIZE as usize
-            };
-            mint_index += 1;
+    pub fn override_lock(ctx: Context<OverrideLock>) -> Result<()> {
+        let balance = &mut ctx.accounts.balance;
+        emit!(LockOverrideEvent {
+            locker: balance.locker,
+            mint: balance.mint,
+            lock_until: balance.lock_until,
+        });
+        balance.lock_until = 0;
+        Ok(())
+    }
+
+    /// Moves items out of a locker to the quarantine treasury, e.g. stolen items.
+    /// Remaining accounts for pNFTs: [token_metadata_program, instructions,
+    /// metadata, token_record, destination_token_record, edition].
+    pub fn clawback<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, Clawback<'info>>,
+        amount: u64,
+        burn_bump: u8,
+        reason: u16,
+    ) -> Result<()> {
+        let accounts = ctx.accounts;
+        if accounts.config.quarantine == Pubkey::default() {
+            return Err(error!(ErrorCode::QuarantineNotSet));
+        }
+        accounts.balance.debit(amount)?;
+
+        let mint = accounts.mint.key();
+        let namespace_seed = accounts.config.namespace_seed();
+        // Clawbacks share the mint rate limit with withdrawals.
+        if let Some(mut mint_policy) =
+            load_mint_policy(&accounts.mint_policy, &mint, &namespace_seed)?
+        {
+            mint_policy.record_withdrawal(amount, Clock::get()?.slot)?;
+            store_mint_policy(&accounts.mint_policy, &mint_policy)?;
         }
+        let burn_signer: &[&[&[u8]]] = &[&[mint.as_ref(), &namespace_seed, &[burn_bump]]];
+        let remaining_accounts = ctx.remaining_accounts;
+        if remaining_accounts.len() == 6 {
+            TransferV1CpiBuilder::new(&remaining_accounts[0])
+                .token(&accounts.burn_ta)
+                .token_owner(&accounts.burn_ta)
+                .destination_token(&accounts.quarantine_ta)
+                .destination_owner(&accounts.quarantine)
+                .token_record(Some(&remaining_accounts[3]))
+                .destination_token_record(Some(&remaining_accounts[4]))
+                .edition(Some(&remaining_accounts[5]))
+                .mint(&accounts.mint.to_account_info())
+                .metadata(&remaining_accounts[2])
+                .authority(&accounts.burn_ta)
+                .payer(&accounts.admin)
+                .system_program(&accounts.system_program)
+                .sysvar_instructions(&remaining_accounts[1])
+                .spl_token_program(&accounts.token_program)
+                .spl_ata_program(&accounts.associated_token_program)
+                .amount(amount)
+                .invoke_signed(burn_signer)?;
+        } else if remaining_accounts.is_empty() {
+            if get_token_account(&accounts.quarantine_ta)?.owner != accounts.config.quarantine {
+                return Err(error!(ErrorCode::InvalidVault));
+            }
+            anchor_spl::token::transfer(
+                CpiContext::new_with_signer(
+                    accounts.token_program.to_account_info(),
+                    anchor_spl::token::Transfer {
+                        from: accounts.burn_ta.to_account_info(),
+                        to: accounts.quarantine_ta.to_account_info(),
+                        authority: accounts.burn_ta.to_account_info(),
+                    },
+                    burn_signer,
+                ),
+                amount,
+            )?;
+        } else {
+            return Err(error!(ErrorCode::WrongRemainingAccountsSize));
+        }
+
+        emit!(ClawbackEvent {
+            locker: accounts.balance.locker,
+            mint,
+            amount,
+            quarantine: accounts.config.quarantine,
+            reason,
+        });
+        Ok(())
+    }
+
+    pub fn init_mint_policy(ctx: Context<InitMintPolicy>) -> Result<()> {
+        let mint_policy = &mut ctx.accounts.mint_policy;
+        mint_policy.mint = ctx.accounts.mint.key();
+        mint_policy.bump = ctx.bumps.mint_policy;
         Ok(())
     }
 
+    /// Tighter limits apply at once, looser ones are queued for
+    /// `MintPolicy::RATE_LIMIT_DELAY` before `apply_withdraw_rate_limit`. A
+    /// tighter limit also drops the queued one.
+    pub fn set_withdraw_rate_limit(
+        ctx: Context<UpdateMintPolicy>,
+        window_slots: u64,
+        window_cap: u64,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        let mint_policy = &mut ctx.accounts.mint_policy;
+        let available_at = if mint_policy.is_tighter(window_slots, window_cap) {
+            mint_policy.apply_rate_limit(window_slots, window_cap, clock.slot);
+            0
+        } else {
+            mint_policy.pending_window_slots = window_slots;
+            mint_policy.pending_window_cap = window_cap;
+            mint_policy.pending_available_at = clock.unix_timestamp + MintPolicy::RATE_LIMIT_DELAY;
+            mint_policy.pending_available_at
+        };
+        emit!(WithdrawRateLimitEvent {
+            mint: mint_policy.mint,
+            window_slots,
+            window_cap,
+            available_at,
+        });
+        Ok(())
+    }
+
+    pub fn apply_withdraw_rate_limit(ctx: Context<UpdateMintPolicy>) -> Result<()> {
+        let clock = Clock::get()?;
+        let mint_policy = &mut ctx.accounts.mint_policy;
+        if mint_policy.pending_available_at == 0
+            || clock.unix_timestamp < mint_policy.pending_available_at
+        {
+            return Err(error!(ErrorCode::RateLimitChangeNotReady));
+        }
+        let (window_slots, window_cap) = (
+            mint_policy.pending_window_slots,
+            mint_policy.pending_window_cap,
+        );
+        mint_policy.apply_rate_limit(window_slots, window_cap, clock.slot);
+        Ok(())
+    }
+
+    pub fn create_vesting_schedule(
+        ctx: Context<CreateVestingSchedule>,
+        total: u64,
+        start: i64,
+        cliff: i64,
+        end: i64,
+        burn_bump: u8,
+    ) -> Result<()> {
+        if total == 0 || start > cliff || cliff > end {
+            return Err(error!(ErrorCode::InvalidVestingSchedule));
+        }
+        let accounts = ctx.accounts;
+        init_pool_ta(
+            &accounts.burn_ta,
+            &accounts.mint.to_account_info(),
+            &accounts.config.namespace_seed(),
+            burn_bump,
+            &accounts.admin,
+            &accounts.system_program,
+            &accounts.token_program,
+            &accounts.rent.to_account_info(),
+        )?;
+        anchor_spl::token::transfer(
+            CpiContext::new(
+                accounts.token_program.to_account_info(),
+                anchor_spl::token::Transfer {
+                    from: accounts.treasury_ta.to_account_info(),
+                    to: accounts.burn_ta.to_account_info(),
+                    authority: accounts.admin.to_account_info(),
+                },
+            ),
+            total,
+        )?;
+
+        let vesting_schedule = &mut accounts.vesting_schedule;
+        vesting_schedule.locker = accounts.locker.key();
+        vesting_schedule.mint = accounts.mint.key();
+        vesting_schedule.start = start;
+        vesting_schedule.cliff = cliff;
+        vesting_schedule.end = end;
+        vesting_schedule.total = total;
+        vesting_schedule.withdrawn = 0;
+        vesting_schedule.bump = ctx.bumps.vesting_schedule;
+        Ok(())
+    }
+
+    /// Funds the pool from the treasury and credits many lockers at once, e.g.
+    /// quest rewards. Remaining accounts are [locker, balance] per amount.
+    pub fn credit_batch<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, CreditBatch<'info>>,
+        amounts: Vec<u64>,
+        burn_bump: u8,
+    ) -> Result<()> {
+        let remaining_accounts: &'c [AccountInfo<'info>] = ctx.remaining_accounts;
+        if remaining_accounts.len() != amounts.len() * 2 {
+            return Err(error!(ErrorCode::WrongRemainingAccountsSize));
+        }
+        let accounts = ctx.accounts;
+        let mint = accounts.mint.key();
+        let total = amounts
+            .iter()
+            .try_fold(0u64, |total, amount| total.checked_add(*amount))
+            .ok_or_else(|| error!(ErrorCode::AmountOverflow))?;
+        init_pool_ta(
+            &accounts.burn_ta,
+            &accounts.mint.to_account_info(),
+            &accounts.config.namespace_seed(),
+            burn_bump,
+            &accounts.admin,
+            &accounts.system_program,
+            &accounts.token_program,
+            &accounts.rent.to_account_info(),
+        )?;
+        anchor_spl::token::transfer(
+            CpiContext::new(
+                accounts.token_program.to_account_info(),
+                anchor_spl::token::Transfer {
+                    from: accounts.treasury_ta.to_account_info(),
+                    to: accounts.burn_ta.to_account_info(),
+                    authority: accounts.admin.to_account_info(),
+                },
+            ),
+            total,
+        )?;
+
+        for (chunk, amount) in remaining_accounts.chunks(2).zip(amounts) {
+            let locker = &chunk[0];
+            if *locker.owner != crate::ID {
+                return Err(error!(ErrorCode::InvalidBalanceAccount));
+            }
+            let locker_data = Locker::try_deserialize(&mut &locker.try_borrow_data()?[..])?;
+            if !accounts.config.has_locker(locker, &locker_data.owner) {
+                return Err(error!(ErrorCode::InvalidLocker));
+            }
+            credit_deposit(
+                &chunk[1],
+                &locker.key(),
+                &mint,
+                amount,
+                None,
+                &accounts.admin,
+                &accounts.system_program,
+            )?;
+            emit!(LockerCreditEvent {
+                locker: locker.key(),
+                mint,
+                amount,
+            });
+        }
+        Ok(())
+    }
+
+    /// Credits holdings deposited into the pool before the ledger existed, from
+    /// the deposit history. Each entry is backfilled once and a call can't
+    /// credit more than the pool holds. Remaining accounts are [locker, balance]
+    /// per amount.
+    pub fn backfill_balances<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, BackfillBalances<'info>>,
+        amounts: Vec<u64>,
+        burn_bump: u8,
+    ) -> Result<()> {
+        let remaining_accounts: &'c [AccountInfo<'info>] = ctx.remaining_accounts;
+        if remaining_accounts.len() != amounts.len() * 2 {
+            return Err(error!(ErrorCode::WrongRemainingAccountsSize));
+        }
+        let accounts = ctx.accounts;
+        let mint = accounts.mint.key();
+        let pool = Pubkey::create_program_address(
+            &[
+                mint.as_ref(),
+                &accounts.config.namespace_seed(),
+                &[burn_bump],
+            ],
+            &crate::ID,
+        )
+        .map_err(|_| error!(ErrorCode::InvalidVault))?;
+        if pool != accounts.burn_ta.key() {
+            return Err(error!(ErrorCode::InvalidVault));
+        }
+        let total = amounts
+            .iter()
+            .try_fold(0u64, |total, amount| total.checked_add(*amount))
+            .ok_or_else(|| error!(ErrorCode::AmountOverflow))?;
+        if total > get_token_account(&accounts.burn_ta)?.amount {
+            return Err(error!(ErrorCode::InsufficientFunds));
+        }
+
+        let payer = accounts.admin.to_account_info();
+        let system_program = accounts.system_program.to_account_info();
+        for (chunk, amount) in remaining_accounts.chunks(2).zip(amounts) {
+            let locker = &chunk[0];
+            if *locker.owner != crate::ID {
+                return Err(error!(ErrorCode::InvalidBalanceAccount));
+            }
+            let locker_data = Locker::try_deserialize(&mut &locker.try_borrow_data()?[..])?;
+            if !accounts.config.has_locker(locker, &locker_data.owner) {
+                return Err(error!(ErrorCode::InvalidLocker));
+            }
+            let mut balance =
+                load_or_init_balance(&chunk[1], locker.key, &mint, &payer, &system_program)?;
+            if balance.backfilled {
+                return Err(error!(ErrorCode::BalanceAlreadyBackfilled));
+            }
+            balance.credit(amount)?;
+            balance.backfilled = true;
+            store_balance(&chunk[1], &balance)?;
+            emit!(LockerCreditEvent {
+                locker: locker.key(),
+                mint,
+                amount,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn close_backfill(ctx: Context<UpdateConfig>) -> Result<()> {
+        ctx.accounts.config.backfill_closed = true;
+        Ok(())
+    }
+
+    /// Remaining accounts follow `finalize_withdraw`.
+    pub fn withdraw_vested<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, WithdrawVested<'info>>,
+        amount: u64,
+        vault_bump: u8,
+        burn_bump: u8,
+    ) -> Result<()> {
+        const PNFT_CHUNK_SIZE: usize = 11;
+        const NORMAL_CHUNK_SIZE: usize = 7;
+        let remaining_accounts: &'c [AccountInfo<'info>] = ctx.remaining_accounts;
+        if remaining_accounts.len() != 2 + NORMAL_CHUNK_SIZE
+            && remaining_accounts.len() != 2 + PNFT_CHUNK_SIZE
+        {
+            return Err(error!(ErrorCode::WrongRemainingAccountsSize));
+        }
+        let accounts: &'b mut WithdrawVested<'info> = ctx.accounts;
+        check_cpi_caller(&accounts.config, &remaining_accounts[1])?;
+        let vesting_schedule = &mut accounts.vesting_schedule;
+        let mint = &remaining_accounts[2];
+        if mint.key() != vesting_schedule.mint
+            || remaining_accounts[5].key() != accounts.owner.key()
+        {
+            return Err(error!(ErrorCode::InvalidVestingSchedule));
+        }
+        let now = Clock::get()?.unix_timestamp;
+        if amount > vesting_schedule.withdrawable_amount(now) {
+            return Err(error!(ErrorCode::VestingAmountExceeded));
+        }
+        vesting_schedule.withdrawn += amount;
+        let mint_policy = load_mint_policy(
+            &remaining_accounts[7],
+            &mint.key(),
+            &accounts.config.namespace_seed(),
+        )?;
+        record_daily_withdrawal(
+            &accounts.balance,
+            &accounts.locker,
+            &mint.key(),
+            amount,
+            &accounts.config,
+            mint_policy.as_ref(),
+            &accounts.owner,
+            &accounts.system_program,
+        )?;
+
+        let mut pd = PerformWithdrawV2 {
+            config: &mut accounts.config,
+            locker: &mut accounts.locker,
+            mint,
+            user_ta_owner: &accounts.owner,
+            recipient: accounts.owner.as_ref(),
+            user_ta: &remaining_accounts[3],
+            vault_ta: &remaining_accounts[4],
+            vault_ta_owner: &remaining_accounts[5],
+            burn_ta: &remaining_accounts[6],
+            balance: None,
+            mint_policy: &remaining_accounts[7],
+            delayed: false,
+            treasury: &accounts.treasury,
+            treasury_ta: &remaining_accounts[8],
+            metadata: None,
+            token_record: None,
+            destination_token_record: None,
+            edition: None,
+            token_metadata_program: &remaining_accounts[0],
+            instructions: &remaining_accounts[1],
+            system_program: &accounts.system_program,
+            token_program: &accounts.token_program,
+            associated_token_program: &accounts.associated_token_program,
+            rent: &accounts.rent,
+        };
+        if remaining_accounts.len() == 2 + PNFT_CHUNK_SIZE {
+            pd.metadata = Some(&remaining_accounts[9]);
+            pd.token_record = Some(&remaining_accounts[10]);
+            pd.destination_token_record = Some(&remaining_accounts[11]);
+            pd.edition = Some(&remaining_accounts[12]);
+        }
+        perform_withdraw_v2(pd, amount, vault_bump, burn_bump)
+    }
+
+    pub fn request_withdraw(ctx: Context<RequestWithdraw>, amount: u64, nonce: u64) -> Result<()> {
+        let accounts = ctx.accounts;
+        if accounts.locker.space != nonce {
+            return Err(error!(ErrorCode::InvalidBeforeState));
+        }
+        accounts.locker.space += 1;
+
+        let locker = accounts.locker.key();
+        let mint = accounts.mint.key();
+        let mint_policy = load_mint_policy(
+            &accounts.mint_policy,
+            &mint,
+            &accounts.config.namespace_seed(),
+        )?;
+        debit_withdrawal(
+            &accounts.balance,
+            &accounts.locker,
+            &mint,
+            amount,
+            &accounts.config,
+            mint_policy.as_ref(),
+            &accounts.owner,
+            &accounts.system_program,
+        )?;
+        let withdraw_delay = mint_policy.map_or(0, |policy| policy.withdraw_delay);
+
+        let pending_withdrawal = &mut accounts.pending_withdrawal;
+        pending_withdrawal.locker = locker;
+        pending_withdrawal.mint = mint;
+        pending_withdrawal.amount = amount;
+        pending_withdrawal.requested_at = Clock::get()?.unix_timestamp;
+        pending_withdrawal.available_at = pending_withdrawal.requested_at + withdraw_delay;
+        emit!(WithdrawRequestEvent {
+            locker,
+            mint,
+            amount,
+            available_at: pending_withdrawal.available_at,
+        });
+        Ok(())
+    }
+
+    /// Remaining accounts follow `redeem_voucher`, with the mint policy in place
+    /// of the balance account.
+    pub fn finalize_withdraw<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, FinalizeWithdraw<'info>>,
+        vault_bump: u8,
+        burn_bump: u8,
+    ) -> Result<()> {
+        const PNFT_CHUNK_SIZE: usize = 11;
+        const NORMAL_CHUNK_SIZE: usize = 7;
+        let remaining_accounts: &'c [AccountInfo<'info>] = ctx.remaining_accounts;
+        if remaining_accounts.len() != 2 + NORMAL_CHUNK_SIZE
+            && remaining_accounts.len() != 2 + PNFT_CHUNK_SIZE
+        {
+            return Err(error!(ErrorCode::WrongRemainingAccountsSize));
+        }
+        let accounts: &'b mut FinalizeWithdraw<'info> = ctx.accounts;
+        check_cpi_caller(&accounts.config, &remaining_accounts[1])?;
+        let pending_withdrawal = &accounts.pending_withdrawal;
+        if Clock::get()?.unix_timestamp < pending_withdrawal.available_at {
+            return Err(error!(ErrorCode::WithdrawalDelayNotElapsed));
+        }
+        let mint = &remaining_accounts[2];
+        if mint.key() != pending_withdrawal.mint
+            || remaining_accounts[5].key() != accounts.owner.key()
+        {
+            return Err(error!(ErrorCode::InvalidVault));
+        }
+        let amount = pending_withdrawal.amount;
+
+        let mut pd = PerformWithdrawV2 {
+            config: &mut accounts.config,
+            locker: &mut accounts.locker,
+            mint,
+            user_ta_owner: &accounts.owner,
+            recipient: accounts.owner.as_ref(),
+            user_ta: &remaining_accounts[3],
+            vault_ta: &remaining_accounts[4],
+            vault_ta_owner: &remaining_accounts[5],
+            burn_ta: &remaining_accounts[6],
+            balance: None,
+            mint_policy: &remaining_accounts[7],
+            delayed: true,
+            treasury: &accounts.treasury,
+            treasury_ta: &remaining_accounts[8],
+            metadata: None,
+            token_record: None,
+            destination_token_record: None,
+            edition: None,
+            token_metadata_program: &remaining_accounts[0],
+            instructions: &remaining_accounts[1],
+            system_program: &accounts.system_program,
+            token_program: &accounts.token_program,
+            associated_token_program: &accounts.associated_token_program,
+            rent: &accounts.rent,
+        };
+        if remaining_accounts.len() == 2 + PNFT_CHUNK_SIZE {
+            pd.metadata = Some(&remaining_accounts[9]);
+            pd.token_record = Some(&remaining_accounts[10]);
+            pd.destination_token_record = Some(&remaining_accounts[11]);
+            pd.edition = Some(&remaining_accounts[12]);
+        }
+        perform_withdraw_v2(pd, amount, vault_bump, burn_bump)
+    }
+
+    pub fn cancel_withdraw(ctx: Context<CancelWithdraw>) -> Result<()> {
+        let pending_withdrawal = &ctx.accounts.pending_withdrawal;
+        let balance = &mut ctx.accounts.balance;
+        balance.credit(pending_withdrawal.amount)?;
+        balance.revert_daily_withdrawal(
+            pending_withdrawal.amount,
+            pending_withdrawal.requested_at,
+            Clock::get()?.unix_timestamp,
+        );
+        emit!(WithdrawCancelEvent {
+            locker: pending_withdrawal.locker,
+            mint: pending_withdrawal.mint,
+            amount: pending_withdrawal.amount,
+            cancelled_by: ctx.accounts.authority.key(),
+        });
+        Ok(())
+    }
+
+    pub fn create_offer(
+        ctx: Context<CreateOffer>,
+        id: u64,
+        give: Vec<TradeItem>,
+        want: Vec<TradeItem>,
+        taker_locker: Option<Pubkey>,
+        expiry: i64,
+    ) -> Result<()> {
+        if give.len() > Offer::MAX_ITEMS
+            || want.len() > Offer::MAX_ITEMS
+            || (give.is_empty() && want.is_empty())
+        {
+            return Err(error!(ErrorCode::InvalidOffer));
+        }
+        check_valid_until(Some(ValidUntil::Timestamp(expiry)))?;
+        let offer = &mut ctx.accounts.offer;
+        offer.maker_locker = ctx.accounts.maker_locker.key();
+        offer.taker_locker = taker_locker;
+        offer.id = id;
+        offer.give = give;
+        offer.want = want;
+        offer.expiry = expiry;
+        Ok(())
+    }
+
+    pub fn cancel_offer(_ctx: Context<CancelOffer>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Remaining accounts: `[maker_balance, taker_balance]` for each `give` item,
+    /// then `[taker_balance, maker_balance]` for each `want` item.
+    pub fn accept_offer<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, AcceptOffer<'info>>,
+        give: Vec<TradeItem>,
+        want: Vec<TradeItem>,
+    ) -> Result<()> {
+        let accounts = ctx.accounts;
+        let offer = &accounts.offer;
+        if offer.give != give || offer.want != want {
+            return Err(error!(ErrorCode::OfferTermsMismatch));
+        }
+        check_valid_until(Some(ValidUntil::Timestamp(offer.expiry)))?;
+        let maker_locker = accounts.maker_locker.key();
+        let taker_locker = accounts.taker_locker.key();
+        if matches!(offer.taker_locker, Some(taker) if taker != taker_locker) {
+            return Err(error!(ErrorCode::Unauthorized));
+        }
+        let remaining_accounts: &'c [AccountInfo<'info>] = ctx.remaining_accounts;
+        if remaining_accounts.len() != 2 * (give.len() + want.len()) {
+            return Err(error!(ErrorCode::WrongRemainingAccountsSize));
+        }
+
+        let payer = accounts.owner.to_account_info();
+        let system_program = accounts.system_program.to_account_info();
+        let (give_accounts, want_accounts) = remaining_accounts.split_at(2 * give.len());
+        for (chunk, item) in give_accounts.chunks(2).zip(give) {
+            move_balance(
+                &chunk[0],
+                &chunk[1],
+                &maker_locker,
+                &taker_locker,
+                &item.mint,
+                item.amount,
+                &payer,
+                &system_program,
+            )?;
+        }
+        for (chunk, item) in want_accounts.chunks(2).zip(want) {
+            move_balance(
+                &chunk[0],
+                &chunk[1],
+                &taker_locker,
+                &maker_locker,
+                &item.mint,
+                item.amount,
+                &payer,
+                &system_program,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Moves `total` from the treasury into the pool, claims can't pay out more.
+    pub fn init_distribution(
+        ctx: Context<InitDistribution>,
+        id: u64,
+        merkle_root: [u8; 32],
+        leaf_count: u32,
+        total: u64,
+        burn_bump: u8,
+    ) -> Result<()> {
+        let accounts = ctx.accounts;
+        init_pool_ta(
+            &accounts.burn_ta,
+            &accounts.mint.to_account_info(),
+            &accounts.config.namespace_seed(),
+            burn_bump,
+            &accounts.admin,
+            &accounts.system_program,
+            &accounts.token_program,
+            &accounts.rent.to_account_info(),
+        )?;
+        anchor_spl::token::transfer(
+            CpiContext::new(
+                accounts.token_program.to_account_info(),
+                anchor_spl::token::Transfer {
+                    from: accounts.treasury_ta.to_account_info(),
+                    to: accounts.burn_ta.to_account_info(),
+                    authority: accounts.admin.to_account_info(),
+                },
+            ),
+            total,
+        )?;
+
+        let distribution = &mut accounts.distribution;
+        distribution.id = id;
+        distribution.merkle_root = merkle_root;
+        distribution.leaf_count = leaf_count;
+        distribution.claimed = vec![0; Distribution::bitmap_len(leaf_count)];
+        distribution.mint = accounts.mint.key();
+        distribution.total = total;
+        distribution.claimed_amount = 0;
+        Ok(())
+    }
+
+    pub fn claim(
+        ctx: Context<Claim>,
+        index: u32,
+        amount: u64,
+        proof: Vec<[u8; 32]>,
+        burn_bump: u8,
+    ) -> Result<()> {
+        let accounts = ctx.accounts;
+        check_cpi_caller(&accounts.config, &accounts.instructions)?;
+        let distribution = &mut accounts.distribution;
+        if index >= distribution.leaf_count {
+            return Err(error!(ErrorCode::InvalidMerkleProof));
+        }
+        if distribution.is_claimed(index) {
+            return Err(error!(ErrorCode::AlreadyClaimed));
+        }
+        let leaf = Distribution::leaf(index, accounts.recipient.key, &accounts.mint.key(), amount);
+        if !verify_merkle_proof(&proof, &distribution.merkle_root, leaf) {
+            return Err(error!(ErrorCode::InvalidMerkleProof));
+        }
+        distribution.claimed_amount = distribution
+            .claimed_amount
+            .checked_add(amount)
+            .filter(|claimed| *claimed <= distribution.total)
+            .ok_or_else(|| error!(ErrorCode::DistributionExhausted))?;
+        distribution.set_claimed(index);
+
+        if *accounts.recipient_ta.owner != accounts.token_program.key() {
+            anchor_spl::associated_token::create(CpiContext::new(
+                accounts.associated_token_program.to_account_info(),
+                anchor_spl::associated_token::Create {
+                    payer: accounts.payer.to_account_info(),
+                    associated_token: accounts.recipient_ta.to_account_info(),
+                    authority: accounts.recipient.to_account_info(),
+                    mint: accounts.mint.to_account_info(),
+                    system_program: accounts.system_program.to_account_info(),
+                    token_program: accounts.token_program.to_account_info(),
+                },
+            ))?;
+        }
+        let recipient_ta = get_token_account(&accounts.recipient_ta)?;
+        if recipient_ta.owner != accounts.recipient.key()
+            || recipient_ta.mint != accounts.mint.key()
+        {
+            return Err(error!(ErrorCode::InvalidVault));
+        }
+
+        anchor_spl::token::transfer(
+            CpiContext::new_with_signer(
+                accounts.token_program.to_account_info(),
+                anchor_spl::token::Transfer {
+                    from: accounts.burn_ta.to_account_info(),
+                    to: accounts.recipient_ta.to_account_info(),
+                    authority: accounts.burn_ta.to_account_info(),
+                },
+                &[&[
+                    accounts.mint.key().as_ref(),
+                    &accounts.config.namespace_seed(),
+                    &[burn_bump],
+                ]],
+            ),
+            amount,
+        )
+    }
+
     pub fn inc_nonce<'a, 'b, 'c, 'info>(
         ctx: Context<'a, 'b, 'c, 'info, IncNonce<'info>>,
         nonce: u64,
+        valid_until: Option<ValidUntil>,
     ) -> Result<()> {
-        ctx.accounts.locker.space;
+        check_valid_until(valid_until)?;
         if ctx.accounts.locker.space != nonce {
             return Err(error!(ErrorCode::InvalidBeforeState));
         }
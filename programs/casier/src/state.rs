@@ -1,4 +1,5 @@
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::{keccak, sysvar};
 use anchor_spl::{
     associated_token::AssociatedToken,
     token::{Mint, Token, TokenAccount},
@@ -22,10 +23,13 @@ pub struct PerformDepositV2<'b, 'c, 'info> {
     pub locker: &'c mut Account<'info, Locker>,
     pub mint: &'c AccountInfo<'info>,
     pub owner: &'b Signer<'info>,
+    /// Receives the rent of a drained legacy vault, `owner` may be a delegate.
+    pub locker_owner: &'b AccountInfo<'info>,
     pub admin: &'b Signer<'info>,
     pub user_ta: &'c AccountInfo<'info>,
     pub vault_ta: &'c AccountInfo<'info>,
     pub burn_ta: &'c AccountInfo<'info>,
+    pub balance: &'c AccountInfo<'info>,
     pub metadata: Option<&'c AccountInfo<'info>>,
     pub token_record: Option<&'c AccountInfo<'info>>,
     pub destination_token_record: Option<&'c AccountInfo<'info>>,
@@ -58,12 +62,21 @@ pub struct PerformWithdrawV2<'b, 'c, 'info> {
     pub config: &'b mut Account<'info, Config>,
     pub locker: &'c mut Account<'info, Locker>,
     pub mint: &'c AccountInfo<'info>,
-    pub admin: &'b Signer<'info>,
     pub user_ta_owner: &'b Signer<'info>,
+    /// Locker owner or guild member, `user_ta` must be one of its token accounts.
+    pub recipient: &'b AccountInfo<'info>,
     pub user_ta: &'c AccountInfo<'info>,
     pub vault_ta: &'c AccountInfo<'info>,
     pub vault_ta_owner: &'c AccountInfo<'info>,
     pub burn_ta: &'c AccountInfo<'info>,
+    pub balance: Option<&'c AccountInfo<'info>>,
+    /// `[b"mint_policy", mint, namespace]`, may be uninitialized.
+    pub mint_policy: &'c AccountInfo<'info>,
+    /// Finalizing a `request_withdraw`, the mint withdraw delay already elapsed.
+    pub delayed: bool,
+    /// Collects the withdrawal fees, `treasury_ta` receives the token fee.
+    pub treasury: &'b AccountInfo<'info>,
+    pub treasury_ta: &'c AccountInfo<'info>,
     pub metadata: Option<&'c AccountInfo<'info>>,
     pub token_record: Option<&'c AccountInfo<'info>>,
     pub destination_token_record: Option<&'c AccountInfo<'info>>,
@@ -78,33 +91,57 @@ pub struct PerformWithdrawV2<'b, 'c, 'info> {
 
 #[derive(Accounts)]
 pub struct DepositBatch<'info> {
-    #[account(seeds = [b"config".as_ref()], bump, has_one = admin, constraint = !config.is_frozen)]
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        has_one = admin,
+        constraint = !config.is_frozen,
+    )]
     pub config: Account<'info, Config>,
     #[account(
         mut,
-        seeds = [ owner.key().as_ref() ],
-        bump,
-        has_one = owner,
+        constraint = locker.owner == owner.key() || delegate.is_some() @ ErrorCode::Unauthorized,
+        constraint = config.has_locker(&locker.to_account_info(), &locker.owner) @ ErrorCode::InvalidLocker,
     )]
     pub locker: Account<'info, Locker>,
+    #[account(
+        mut,
+        has_one = locker,
+        constraint = delegate.delegate == owner.key() @ ErrorCode::Unauthorized,
+    )]
+    pub delegate: Option<Account<'info, Delegate>>,
+    /// Locker owner, or the delegate key of `delegate`.
     #[account(mut)]
     pub owner: Signer<'info>,
+    /// CHECK: receives the rent of drained legacy vaults
+    #[account(mut, address = locker.owner)]
+    pub locker_owner: UncheckedAccount<'info>,
     #[account(mut)]
     pub admin: Signer<'info>,
     pub system_program: Program<'info, System>,
     pub token_program: Program<'info, Token>,
     pub rent: Sysvar<'info, Rent>,
+    /// CHECK: collects fees, system owned
+    #[account(mut, seeds = [b"treasury".as_ref(), config.namespace_seed().as_ref()], bump)]
+    pub treasury: UncheckedAccount<'info>,
+    /// CHECK: instructions sysvar
+    #[account(address = sysvar::instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
 }
 
 #[derive(Accounts)]
 pub struct Withdraw<'info> {
-    #[account(seeds = [b"config".as_ref()], bump, has_one = admin, constraint = !config.is_frozen)]
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        has_one = admin,
+        constraint = !config.is_frozen,
+    )]
     pub config: Account<'info, Config>,
     #[account(
         mut,
-        seeds = [ owner.key().as_ref() ],
-        bump,
         has_one = owner,
+        constraint = config.has_locker(&locker.to_account_info(), &locker.owner) @ ErrorCode::InvalidLocker,
     )]
     pub locker: Account<'info, Locker>,
     pub mint: Account<'info, Mint>,
@@ -129,14 +166,32 @@ pub struct Withdraw<'info> {
 
 #[derive(Accounts)]
 pub struct WithdrawV2Batch<'info> {
-    #[account(seeds = [b"config".as_ref()], bump, has_one = admin, constraint = !config.is_frozen)]
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        has_one = admin,
+        constraint = !config.is_frozen,
+    )]
     pub config: Account<'info, Config>,
-    #[account(mut)]
+    #[account(
+        mut,
+        constraint = config.has_locker(&locker.to_account_info(), &locker.owner) @ ErrorCode::InvalidLocker,
+    )]
     pub locker: Account<'info, Locker>,
+    #[account(
+        mut,
+        has_one = locker,
+        constraint = delegate.delegate == user_ta_owner.key() @ ErrorCode::Unauthorized,
+    )]
+    pub delegate: Option<Account<'info, Delegate>>,
     #[account(mut)]
     pub admin: Signer<'info>,
+    /// Locker owner, the delegate key of `delegate` or an approved guild member.
     #[account(mut)]
     pub user_ta_owner: Signer<'info>,
+    /// CHECK: receives the withdrawn items, delegates included
+    #[account(address = locker.owner @ ErrorCode::Unauthorized)]
+    pub locker_owner: UncheckedAccount<'info>,
     /// CHECK:
     #[account(mut)]
     pub vault_ta_owner: AccountInfo<'info>,
@@ -144,13 +199,330 @@ pub struct WithdrawV2Batch<'info> {
     pub token_program: Program<'info, Token>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub rent: Sysvar<'info, Rent>,
+    /// CHECK: collects fees, system owned
+    #[account(mut, seeds = [b"treasury".as_ref(), config.namespace_seed().as_ref()], bump)]
+    pub treasury: UncheckedAccount<'info>,
+    /// CHECK: instructions sysvar
+    #[account(address = sysvar::instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+}
+
+pub struct PerformBurn<'b, 'c, 'info> {
+    pub config: &'b Account<'info, Config>,
+    pub locker: &'b Account<'info, Locker>,
+    pub mint: &'c AccountInfo<'info>,
+    pub burn_ta: &'c AccountInfo<'info>,
+    pub balance: &'c AccountInfo<'info>,
+    /// Pays new ledger entries and receives the rent of emptied pools.
+    pub admin: &'b Signer<'info>,
+    /// Locker owner when it co-signed the burn.
+    pub co_signer: Option<Pubkey>,
+    pub metadata: Option<&'c AccountInfo<'info>>,
+    pub edition: Option<&'c AccountInfo<'info>>,
+    pub token_record: Option<&'c AccountInfo<'info>>,
+    pub token_metadata_program: &'c AccountInfo<'info>,
+    pub instructions: &'c AccountInfo<'info>,
+    pub system_program: &'b Program<'info, System>,
+    pub token_program: &'b Program<'info, Token>,
 }
 
 #[derive(Accounts)]
-pub struct IncNonce<'info> {
-    #[account(seeds = [b"config".as_ref()], bump, has_one = admin, constraint = !config.is_frozen)]
+pub struct BurnBatch<'info> {
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        has_one = admin,
+        constraint = !config.is_frozen,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        constraint = config.has_locker(&locker.to_account_info(), &locker.owner) @ ErrorCode::InvalidLocker,
+    )]
+    pub locker: Account<'info, Locker>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    /// Optional co-signer, the admin alone can burn.
+    #[account(address = locker.owner @ ErrorCode::Unauthorized)]
+    pub owner: Option<Signer<'info>>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(delegate: Pubkey)]
+pub struct CreateDelegate<'info> {
+    #[account(has_one = owner)]
+    pub locker: Account<'info, Locker>,
+    #[account(
+        init,
+        seeds = [b"delegate".as_ref(), locker.key().as_ref(), delegate.as_ref()],
+        bump,
+        payer = owner,
+        space = Delegate::SIZE,
+    )]
+    pub delegate_account: Account<'info, Delegate>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeDelegate<'info> {
+    #[account(has_one = owner)]
+    pub locker: Account<'info, Locker>,
+    #[account(mut, has_one = locker, close = owner)]
+    pub delegate: Account<'info, Delegate>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetRecovery<'info> {
+    #[account(has_one = owner)]
+    pub locker: Account<'info, Locker>,
+    #[account(
+        init,
+        seeds = [b"recovery".as_ref(), locker.key().as_ref()],
+        bump,
+        payer = owner,
+        space = Recovery::SIZE,
+    )]
+    pub recovery: Account<'info, Recovery>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateRecovery<'info> {
+    #[account(has_one = owner)]
+    pub locker: Account<'info, Locker>,
+    #[account(mut, has_one = locker)]
+    pub recovery: Account<'info, Recovery>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitiateRecovery<'info> {
+    #[account(mut, has_one = recovery_key)]
+    pub recovery: Account<'info, Recovery>,
+    pub recovery_key: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RecoverLocker<'info> {
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        constraint = !config.is_frozen,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        constraint = config.has_locker(&locker.to_account_info(), &locker.owner) @ ErrorCode::InvalidLocker,
+    )]
+    pub locker: Account<'info, Locker>,
+    #[account(has_one = locker, has_one = recovery_key)]
+    pub recovery: Account<'info, Recovery>,
+    #[account(
+        constraint = new_locker.key() != locker.key() @ ErrorCode::SameLocker,
+        constraint = new_locker.owner == recovery.new_owner @ ErrorCode::InvalidLocker,
+        constraint = config.has_locker(&new_locker.to_account_info(), &new_locker.owner) @ ErrorCode::InvalidLocker,
+    )]
+    pub new_locker: Account<'info, Locker>,
+    #[account(mut)]
+    pub recovery_key: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct FinalizeRecovery<'info> {
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        constraint = !config.is_frozen,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        constraint = config.has_locker(&locker.to_account_info(), &locker.owner) @ ErrorCode::InvalidLocker,
+    )]
+    pub locker: Account<'info, Locker>,
+    #[account(mut, has_one = locker, has_one = recovery_key, close = recovery_key)]
+    pub recovery: Account<'info, Recovery>,
+    #[account(
+        mut,
+        constraint = new_locker.key() != locker.key() @ ErrorCode::SameLocker,
+        constraint = new_locker.owner == recovery.new_owner @ ErrorCode::InvalidLocker,
+        constraint = config.has_locker(&new_locker.to_account_info(), &new_locker.owner) @ ErrorCode::InvalidLocker,
+    )]
+    pub new_locker: Account<'info, Locker>,
+    #[account(mut)]
+    pub recovery_key: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CreateRental<'info> {
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        constraint = !config.is_frozen,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(constraint = config.has_locker(&lender_locker.to_account_info(), &lender_locker.owner) @ ErrorCode::InvalidLocker,)]
+    pub lender_locker: Account<'info, Locker>,
+    #[account(
+        constraint = borrower_locker.key() != lender_locker.key() @ ErrorCode::SameLocker,
+        constraint = config.has_locker(&borrower_locker.to_account_info(), &borrower_locker.owner) @ ErrorCode::InvalidLocker,
+    )]
+    pub borrower_locker: Account<'info, Locker>,
+    pub mint: Account<'info, Mint>,
+    #[account(
+        init,
+        seeds = [b"rental".as_ref(), lender_locker.key().as_ref(), mint.key().as_ref()],
+        bump,
+        payer = lender_owner,
+        space = Rental::SIZE,
+    )]
+    pub rental: Account<'info, Rental>,
+    #[account(
+        mut,
+        seeds = [b"balance".as_ref(), lender_locker.key().as_ref(), mint.key().as_ref()],
+        bump = lender_balance.bump,
+    )]
+    pub lender_balance: Account<'info, Balance>,
+    #[account(mut)]
+    /// CHECK: seeds checked when loaded
+    pub borrower_balance: UncheckedAccount<'info>,
+    #[account(mut, address = lender_locker.owner)]
+    pub lender_owner: Signer<'info>,
+    #[account(address = borrower_locker.owner)]
+    pub borrower_owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SettleRental<'info> {
+    #[account(
+        mut,
+        has_one = lender_locker,
+        has_one = borrower_locker,
+        close = lender_owner,
+    )]
+    pub rental: Account<'info, Rental>,
+    pub lender_locker: Account<'info, Locker>,
+    pub borrower_locker: Account<'info, Locker>,
+    #[account(
+        mut,
+        seeds = [b"balance".as_ref(), lender_locker.key().as_ref(), rental.mint.as_ref()],
+        bump = lender_balance.bump,
+    )]
+    pub lender_balance: Account<'info, Balance>,
+    #[account(
+        mut,
+        seeds = [b"balance".as_ref(), borrower_locker.key().as_ref(), rental.mint.as_ref()],
+        bump = borrower_balance.bump,
+    )]
+    pub borrower_balance: Account<'info, Balance>,
+    /// CHECK: receives the rental rent back
+    #[account(mut, address = lender_locker.owner)]
+    pub lender_owner: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SweepFees<'info> {
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        has_one = admin,
+    )]
+    pub config: Account<'info, Config>,
+    /// CHECK: collects fees, system owned
+    #[account(mut, seeds = [b"treasury".as_ref(), config.namespace_seed().as_ref()], bump)]
+    pub treasury: UncheckedAccount<'info>,
+    /// CHECK: receives the collected lamports
+    #[account(mut)]
+    pub destination: UncheckedAccount<'info>,
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ReclaimRent<'info> {
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        has_one = admin,
+        has_one = rent_recipient,
+    )]
+    pub config: Account<'info, Config>,
+    /// CHECK: checked against the config
+    #[account(mut)]
+    pub rent_recipient: UncheckedAccount<'info>,
+    pub admin: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateLegacyVaults<'info> {
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        has_one = admin,
+        constraint = config.namespace == 0 @ ErrorCode::InvalidVault,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct GetBalances<'info> {
+    pub locker: Account<'info, Locker>,
+}
+
+#[derive(Accounts)]
+pub struct RedeemVoucher<'info> {
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        constraint = !config.is_frozen,
+    )]
     pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        has_one = owner,
+        constraint = config.has_locker(&locker.to_account_info(), &locker.owner) @ ErrorCode::InvalidLocker,
+    )]
+    pub locker: Account<'info, Locker>,
     #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub rent: Sysvar<'info, Rent>,
+    /// CHECK: collects fees, system owned
+    #[account(mut, seeds = [b"treasury".as_ref(), config.namespace_seed().as_ref()], bump)]
+    pub treasury: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct IncNonce<'info> {
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        has_one = admin,
+        constraint = !config.is_frozen,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        constraint = config.has_locker(&locker.to_account_info(), &locker.owner) @ ErrorCode::InvalidLocker,
+    )]
     pub locker: Account<'info, Locker>,
     #[account(mut)]
     pub admin: Signer<'info>,
@@ -158,13 +530,17 @@ pub struct IncNonce<'info> {
 
 #[derive(Accounts)]
 pub struct WithdrawAndBurn<'info> {
-    #[account(seeds = [b"config".as_ref()], bump, has_one = admin, constraint = !config.is_frozen)]
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        has_one = admin,
+        constraint = !config.is_frozen,
+    )]
     pub config: Account<'info, Config>,
     #[account(
         mut,
-        seeds = [ owner.key().as_ref() ],
-        bump,
         has_one = owner,
+        constraint = config.has_locker(&locker.to_account_info(), &locker.owner) @ ErrorCode::InvalidLocker,
     )]
     pub locker: Account<'info, Locker>,
     pub mint: Account<'info, Mint>,
@@ -191,30 +567,749 @@ pub struct WithdrawAndBurn<'info> {
 }
 
 #[derive(Accounts)]
-pub struct InitConfig<'info> {
-    #[account(init, seeds = [b"config".as_ref()], bump, payer = fee_payer, space = 8 + 32 + 1)]
+pub struct TransferBetweenLockers<'info> {
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        constraint = !config.is_frozen,
+    )]
     pub config: Account<'info, Config>,
+    #[account(
+        constraint = from_locker.key() != to_locker.key() @ ErrorCode::SameLocker,
+        constraint = config.has_locker(&from_locker.to_account_info(), &from_locker.owner) @ ErrorCode::InvalidLocker,
+    )]
+    pub from_locker: Account<'info, Locker>,
+    #[account(
+        constraint = config.has_locker(&to_locker.to_account_info(), &to_locker.owner) @ ErrorCode::InvalidLocker,
+    )]
+    pub to_locker: Account<'info, Locker>,
+    /// CHECK: signs together with to_owner unless the admin does
+    #[account(address = from_locker.owner)]
+    pub from_owner: UncheckedAccount<'info>,
+    /// CHECK: signs together with from_owner unless the admin does
+    #[account(address = to_locker.owner)]
+    pub to_owner: UncheckedAccount<'info>,
+    /// CHECK: signs in place of both owners
+    #[account(address = config.admin)]
+    pub admin: UncheckedAccount<'info>,
     #[account(mut)]
-    pub fee_payer: Signer<'info>,
+    pub payer: Signer<'info>,
     pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
 }
 
 #[derive(Accounts)]
-pub struct InitLockerV2<'info> {
-    #[account(init, seeds = [owner.key().as_ref()], bump, payer = owner, space = Locker::MAX_SIZE)]
+pub struct OverrideLock<'info> {
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        has_one = admin,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        constraint = config.has_locker(&locker.to_account_info(), &locker.owner) @ ErrorCode::InvalidLocker,
+    )]
+    pub locker: Account<'info, Locker>,
+    #[account(
+        mut,
+        seeds = [b"balance".as_ref(), locker.key().as_ref(), balance.mint.as_ref()],
+        bump = balance.bump,
+    )]
+    pub balance: Account<'info, Balance>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct Clawback<'info> {
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        has_one = admin,
+        has_one = quarantine,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        constraint = config.has_locker(&locker.to_account_info(), &locker.owner) @ ErrorCode::InvalidLocker,
+    )]
     pub locker: Account<'info, Locker>,
+    #[account(
+        mut,
+        seeds = [b"balance".as_ref(), locker.key().as_ref(), mint.key().as_ref()],
+        bump = balance.bump,
+    )]
+    pub balance: Account<'info, Balance>,
+    pub mint: Account<'info, Mint>,
     #[account(mut)]
-    pub owner: Signer<'info>,
+    /// CHECK: seeds checked in the transfer signature
+    pub burn_ta: UncheckedAccount<'info>,
+    /// CHECK: checked against the config
+    pub quarantine: UncheckedAccount<'info>,
+    #[account(mut)]
+    /// CHECK: owner checked in handler, created by token metadata for pNFTs
+    pub quarantine_ta: UncheckedAccount<'info>,
+    #[account(mut)]
+    /// CHECK: seeds checked when loaded
+    pub mint_policy: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
     pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
 }
 
-#[account]
-#[derive(Default)]
+#[derive(Accounts)]
+pub struct InitMintPolicy<'info> {
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        has_one = admin,
+    )]
+    pub config: Account<'info, Config>,
+    pub mint: Account<'info, Mint>,
+    #[account(
+        init,
+        seeds = [
+            b"mint_policy".as_ref(),
+            mint.key().as_ref(),
+            config.namespace_seed().as_ref(),
+        ],
+        bump,
+        payer = admin,
+        space = MintPolicy::SIZE,
+    )]
+    pub mint_policy: Account<'info, MintPolicy>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateMintPolicy<'info> {
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        has_one = admin,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [
+            b"mint_policy".as_ref(),
+            mint_policy.mint.as_ref(),
+            config.namespace_seed().as_ref(),
+        ],
+        bump = mint_policy.bump,
+    )]
+    pub mint_policy: Account<'info, MintPolicy>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CreateVestingSchedule<'info> {
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        has_one = admin,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        constraint = config.has_locker(&locker.to_account_info(), &locker.owner) @ ErrorCode::InvalidLocker,
+    )]
+    pub locker: Account<'info, Locker>,
+    pub mint: Account<'info, Mint>,
+    #[account(
+        init,
+        seeds = [b"vesting".as_ref(), locker.key().as_ref(), mint.key().as_ref()],
+        bump,
+        payer = admin,
+        space = VestingSchedule::SIZE,
+    )]
+    pub vesting_schedule: Account<'info, VestingSchedule>,
+    #[account(mut, token::mint = mint, token::authority = admin)]
+    pub treasury_ta: Account<'info, TokenAccount>,
+    #[account(mut)]
+    /// CHECK: initialized if needed, seeds checked in handler
+    pub burn_ta: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct CreditBatch<'info> {
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        has_one = admin,
+    )]
+    pub config: Account<'info, Config>,
+    pub mint: Account<'info, Mint>,
+    #[account(mut, token::mint = mint, token::authority = admin)]
+    pub treasury_ta: Account<'info, TokenAccount>,
+    #[account(mut)]
+    /// CHECK: initialized if needed, seeds checked in handler
+    pub burn_ta: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct BackfillBalances<'info> {
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        has_one = admin,
+        constraint = !config.backfill_closed @ ErrorCode::BackfillClosed,
+    )]
+    pub config: Account<'info, Config>,
+    pub mint: Account<'info, Mint>,
+    /// CHECK: seeds checked in handler
+    pub burn_ta: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawVested<'info> {
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        constraint = !config.is_frozen,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        has_one = owner,
+        constraint = config.has_locker(&locker.to_account_info(), &locker.owner) @ ErrorCode::InvalidLocker,
+    )]
+    pub locker: Account<'info, Locker>,
+    #[account(
+        mut,
+        seeds = [b"vesting".as_ref(), locker.key().as_ref(), vesting_schedule.mint.as_ref()],
+        bump = vesting_schedule.bump,
+        has_one = locker,
+    )]
+    pub vesting_schedule: Account<'info, VestingSchedule>,
+    #[account(mut)]
+    /// CHECK: seeds checked when loaded, only records the daily cap
+    pub balance: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub rent: Sysvar<'info, Rent>,
+    /// CHECK: collects fees, system owned
+    #[account(mut, seeds = [b"treasury".as_ref(), config.namespace_seed().as_ref()], bump)]
+    pub treasury: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RequestWithdraw<'info> {
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        has_one = admin,
+        constraint = !config.is_frozen,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        has_one = owner,
+        constraint = config.has_locker(&locker.to_account_info(), &locker.owner) @ ErrorCode::InvalidLocker,
+    )]
+    pub locker: Account<'info, Locker>,
+    pub mint: Account<'info, Mint>,
+    #[account(
+        init,
+        seeds = [b"pending_withdraw".as_ref(), locker.key().as_ref(), mint.key().as_ref()],
+        bump,
+        payer = owner,
+        space = PendingWithdrawal::SIZE,
+    )]
+    pub pending_withdrawal: Account<'info, PendingWithdrawal>,
+    #[account(mut)]
+    /// CHECK: seeds checked when loaded
+    pub balance: UncheckedAccount<'info>,
+    /// CHECK: seeds checked when loaded
+    pub mint_policy: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct FinalizeWithdraw<'info> {
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        constraint = !config.is_frozen,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        has_one = owner,
+        constraint = config.has_locker(&locker.to_account_info(), &locker.owner) @ ErrorCode::InvalidLocker,
+    )]
+    pub locker: Account<'info, Locker>,
+    #[account(mut, has_one = locker, close = owner)]
+    pub pending_withdrawal: Account<'info, PendingWithdrawal>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub rent: Sysvar<'info, Rent>,
+    /// CHECK: collects fees, system owned
+    #[account(mut, seeds = [b"treasury".as_ref(), config.namespace_seed().as_ref()], bump)]
+    pub treasury: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CancelWithdraw<'info> {
+    #[account(seeds = [b"config".as_ref(), config.namespace_seed().as_ref()], bump)]
+    pub config: Account<'info, Config>,
+    #[account(
+        constraint = config.has_locker(&locker.to_account_info(), &locker.owner) @ ErrorCode::InvalidLocker,
+    )]
+    pub locker: Account<'info, Locker>,
+    #[account(mut, has_one = locker, close = owner)]
+    pub pending_withdrawal: Account<'info, PendingWithdrawal>,
+    #[account(
+        mut,
+        seeds = [b"balance".as_ref(), locker.key().as_ref(), pending_withdrawal.mint.as_ref()],
+        bump = balance.bump,
+    )]
+    pub balance: Account<'info, Balance>,
+    /// CHECK: receives the pending withdrawal rent back
+    #[account(mut, address = locker.owner)]
+    pub owner: UncheckedAccount<'info>,
+    #[account(
+        constraint = authority.key() == locker.owner || authority.key() == config.admin @ ErrorCode::Unauthorized,
+    )]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64)]
+pub struct CreateOffer<'info> {
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        constraint = !config.is_frozen,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        has_one = owner,
+        constraint = config.has_locker(&maker_locker.to_account_info(), &maker_locker.owner) @ ErrorCode::InvalidLocker,
+    )]
+    pub maker_locker: Account<'info, Locker>,
+    #[account(
+        init,
+        seeds = [b"offer".as_ref(), maker_locker.key().as_ref(), id.to_le_bytes().as_ref()],
+        bump,
+        payer = owner,
+        space = Offer::SIZE,
+    )]
+    pub offer: Account<'info, Offer>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelOffer<'info> {
+    #[account(has_one = owner)]
+    pub maker_locker: Account<'info, Locker>,
+    #[account(mut, has_one = maker_locker, close = owner)]
+    pub offer: Account<'info, Offer>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptOffer<'info> {
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        constraint = !config.is_frozen,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut, has_one = maker_locker, close = maker_owner)]
+    pub offer: Account<'info, Offer>,
+    #[account(
+        constraint = maker_locker.key() != taker_locker.key() @ ErrorCode::SameLocker,
+        constraint = config.has_locker(&maker_locker.to_account_info(), &maker_locker.owner) @ ErrorCode::InvalidLocker,
+    )]
+    pub maker_locker: Account<'info, Locker>,
+    /// CHECK: receives the offer rent back
+    #[account(mut, address = maker_locker.owner)]
+    pub maker_owner: UncheckedAccount<'info>,
+    #[account(
+        has_one = owner,
+        constraint = config.has_locker(&taker_locker.to_account_info(), &taker_locker.owner) @ ErrorCode::InvalidLocker,
+    )]
+    pub taker_locker: Account<'info, Locker>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64, merkle_root: [u8; 32], leaf_count: u32)]
+pub struct InitDistribution<'info> {
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        has_one = admin,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        init,
+        seeds = [
+            b"distribution".as_ref(),
+            id.to_le_bytes().as_ref(),
+            config.namespace_seed().as_ref(),
+        ],
+        bump,
+        payer = admin,
+        space = Distribution::space(leaf_count),
+        constraint = leaf_count > 0 && leaf_count <= Distribution::MAX_LEAF_COUNT @ ErrorCode::InvalidLeafCount,
+    )]
+    pub distribution: Account<'info, Distribution>,
+    pub mint: Account<'info, Mint>,
+    #[account(mut, token::mint = mint, token::authority = admin)]
+    pub treasury_ta: Account<'info, TokenAccount>,
+    #[account(mut)]
+    /// CHECK: initialized if needed, seeds checked in handler
+    pub burn_ta: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct Claim<'info> {
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        constraint = !config.is_frozen,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [
+            b"distribution".as_ref(),
+            distribution.id.to_le_bytes().as_ref(),
+            config.namespace_seed().as_ref(),
+        ],
+        bump,
+        has_one = mint,
+    )]
+    pub distribution: Account<'info, Distribution>,
+    pub mint: Account<'info, Mint>,
+    /// CHECK: leaf recipient, bound by the merkle proof
+    pub recipient: UncheckedAccount<'info>,
+    #[account(mut)]
+    /// CHECK: created as the recipient ATA if needed
+    pub recipient_ta: UncheckedAccount<'info>,
+    #[account(mut)]
+    /// CHECK: seeds checked on transfer
+    pub burn_ta: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    /// CHECK: instructions sysvar
+    #[account(address = sysvar::instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitConfig<'info> {
+    #[account(init, seeds = [b"config".as_ref()], bump, payer = fee_payer, space = Config::SIZE)]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub fee_payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(namespace: u32)]
+pub struct InitNamespaceConfig<'info> {
+    #[account(seeds = [b"config".as_ref()], bump, has_one = admin)]
+    pub root_config: Account<'info, Config>,
+    #[account(
+        init,
+        seeds = [b"config".as_ref(), namespace_seed(namespace).as_ref()],
+        bump,
+        payer = admin,
+        space = Config::SIZE,
+        constraint = namespace != 0 @ ErrorCode::InvalidBeforeState,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateConfig<'info> {
+    #[account(mut, seeds = [b"config".as_ref()], bump)]
+    /// CHECK: may still use a previous layout, admin is read from the raw data
+    pub config: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    #[account(
+        mut,
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        has_one = admin,
+    )]
+    pub config: Account<'info, Config>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RaiseLockerDailyCap<'info> {
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        has_one = admin,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        constraint = config.has_locker(&locker.to_account_info(), &locker.owner) @ ErrorCode::InvalidLocker,
+        realloc = locker.cap_size(&locker.to_account_info()),
+        realloc::payer = admin,
+        realloc::zero = false,
+    )]
+    pub locker: Account<'info, Locker>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitLockerV2<'info> {
+    #[account(init, seeds = [owner.key().as_ref()], bump, payer = owner, space = Locker::MAX_SIZE)]
+    pub locker: Account<'info, Locker>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct InitNamespaceLocker<'info> {
+    #[account(seeds = [b"config".as_ref(), config.namespace_seed().as_ref()], bump)]
+    pub config: Account<'info, Config>,
+    #[account(
+        init,
+        seeds = [owner.key().as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        payer = owner,
+        space = Locker::MAX_SIZE,
+    )]
+    pub locker: Account<'info, Locker>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(index: u16)]
+pub struct InitLockerV3<'info> {
+    #[account(seeds = [b"config".as_ref(), config.namespace_seed().as_ref()], bump)]
+    pub config: Account<'info, Config>,
+    #[account(
+        init,
+        seeds = [
+            b"locker".as_ref(),
+            owner.key().as_ref(),
+            index.to_le_bytes().as_ref(),
+            config.namespace_seed().as_ref(),
+        ],
+        bump,
+        payer = owner,
+        space = Locker::V3_SIZE,
+    )]
+    pub locker: Account<'info, Locker>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64)]
+pub struct InitGuild<'info> {
+    #[account(seeds = [b"config".as_ref(), config.namespace_seed().as_ref()], bump)]
+    pub config: Account<'info, Config>,
+    #[account(
+        init,
+        seeds = [
+            b"guild".as_ref(),
+            id.to_le_bytes().as_ref(),
+            config.namespace_seed().as_ref(),
+        ],
+        bump,
+        payer = payer,
+        space = Guild::SIZE,
+    )]
+    pub guild: Account<'info, Guild>,
+    #[account(
+        init,
+        seeds = [guild.key().as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        payer = payer,
+        space = Locker::MAX_SIZE,
+    )]
+    pub locker: Account<'info, Locker>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateGuild<'info> {
+    #[account(mut)]
+    pub guild: Account<'info, Guild>,
+}
+
+#[derive(Accounts)]
+pub struct GuildDepositBatch<'info> {
+    #[account(
+        seeds = [b"config".as_ref(), config.namespace_seed().as_ref()],
+        bump,
+        has_one = admin,
+        constraint = !config.is_frozen,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut, constraint = guild.members.contains(&depositor.key()) @ ErrorCode::Unauthorized)]
+    pub guild: Account<'info, Guild>,
+    #[account(
+        mut,
+        constraint = locker.owner == guild.key() @ ErrorCode::InvalidLocker,
+        constraint = config.has_locker(&locker.to_account_info(), &locker.owner) @ ErrorCode::InvalidLocker,
+    )]
+    pub locker: Account<'info, Locker>,
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub rent: Sysvar<'info, Rent>,
+    /// CHECK: collects fees, system owned
+    #[account(mut, seeds = [b"treasury".as_ref(), config.namespace_seed().as_ref()], bump)]
+    pub treasury: UncheckedAccount<'info>,
+    /// CHECK: instructions sysvar
+    #[account(address = sysvar::instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct GuildWithdrawBatch<'info> {
+    pub withdraw: WithdrawV2Batch<'info>,
+    #[account(
+        address = withdraw.locker.owner,
+        constraint = guild.members.contains(&withdraw.user_ta_owner.key()) @ ErrorCode::Unauthorized,
+    )]
+    pub guild: Account<'info, Guild>,
+}
+
+/// Extra seed of the accounts of a namespace. Empty for namespace 0 so that the
+/// addresses predating namespaces stay valid.
+pub fn namespace_seed(namespace: u32) -> Vec<u8> {
+    if namespace == 0 {
+        Vec::new()
+    } else {
+        namespace.to_le_bytes().to_vec()
+    }
+}
+
+#[account]
+#[derive(Default)]
 pub struct Config {
     pub admin: Pubkey,
     pub is_frozen: bool,
+    /// Max amount of a mint a locker can withdraw per day, 0 for no cap.
+    pub locker_daily_cap: u64,
+    /// Receives clawed back items, clawback is disabled while unset. It can
+    /// only be set once.
+    pub quarantine: Pubkey,
+    /// Game sharing the program, each with its own config, lockers and pools.
+    pub namespace: u32,
+    /// Which programs may invoke deposits and withdrawals through CPI.
+    pub cpi_guard: u8,
+    pub cpi_allowlist: Vec<Pubkey>,
+    /// Lamports charged per item deposited or withdrawn through the batches.
+    pub deposit_fee: u64,
+    pub withdraw_fee: u64,
+    /// Receives the rent of emptied pools and vaults, reclaim is disabled while unset.
+    pub rent_recipient: Pubkey,
+    /// Set once holdings predating the ledger are credited, `backfill_balances`
+    /// is disabled for good.
+    pub backfill_closed: bool,
+}
+
+impl Config {
+    pub const SIZE: usize = 8 + // Discriminator
+    32 + // Admin
+    1 + // Is frozen
+    8 + // Locker daily cap
+    32 + // Quarantine
+    4 + // Namespace
+    1 + // CPI guard
+    4 + 32 * Self::MAX_CPI_CALLERS + // CPI allowlist
+    8 + // Deposit fee
+    8 + // Withdraw fee
+    32 + // Rent recipient
+    1; // Backfill closed
+
+    pub const CPI_OPEN: u8 = 0;
+    pub const CPI_ALLOWLIST: u8 = 1;
+    pub const CPI_DISABLED: u8 = 2;
+    pub const MAX_CPI_CALLERS: usize = 8;
+
+    pub fn namespace_seed(&self) -> Vec<u8> {
+        namespace_seed(self.namespace)
+    }
+
+    pub fn has_locker(&self, locker: &AccountInfo, owner: &Pubkey) -> bool {
+        let namespace_seed = self.namespace_seed();
+        let (address, _) = match Locker::index(locker) {
+            Some(index) => Pubkey::find_program_address(
+                &[
+                    b"locker".as_ref(),
+                    owner.as_ref(),
+                    index.to_le_bytes().as_ref(),
+                    namespace_seed.as_ref(),
+                ],
+                &crate::ID,
+            ),
+            None => {
+                Pubkey::find_program_address(&[owner.as_ref(), namespace_seed.as_ref()], &crate::ID)
+            }
+        };
+        address == locker.key()
+    }
 }
 #[account]
 #[derive(Default)]
@@ -226,6 +1321,672 @@ pub struct Locker {
     pub space: u64,
 }
 
+#[account]
+#[derive(Default)]
+pub struct Distribution {
+    pub id: u64,
+    pub merkle_root: [u8; 32],
+    pub leaf_count: u32,
+    pub claimed: Vec<u8>,
+    pub mint: Pubkey,
+    /// Amount moved into the pool when the distribution was created.
+    pub total: u64,
+    pub claimed_amount: u64,
+}
+
+impl Distribution {
+    // Keeps the account under the 10KiB CPI allocation limit.
+    pub const MAX_LEAF_COUNT: u32 = 80_000;
+
+    pub fn space(leaf_count: u32) -> usize {
+        8 + // Discriminator
+        8 + // Id
+        32 + // Merkle root
+        4 + // Leaf count
+        4 + Self::bitmap_len(leaf_count) + // Claimed bitmap
+        32 + // Mint
+        8 + // Total
+        8 // Claimed amount
+    }
+
+    pub fn bitmap_len(leaf_count: u32) -> usize {
+        let leaf_count = leaf_count as usize;
+        leaf_count / 8 + usize::from(leaf_count % 8 != 0)
+    }
+
+    pub fn is_claimed(&self, index: u32) -> bool {
+        self.claimed[(index / 8) as usize] & (1 << (index % 8)) != 0
+    }
+
+    pub fn set_claimed(&mut self, index: u32) {
+        self.claimed[(index / 8) as usize] |= 1 << (index % 8);
+    }
+
+    /// Leaves are `keccak(0x00 || index || recipient || mint || amount)`.
+    pub fn leaf(index: u32, recipient: &Pubkey, mint: &Pubkey, amount: u64) -> [u8; 32] {
+        keccak::hashv(&[
+            &[0u8],
+            &index.to_le_bytes(),
+            recipient.as_ref(),
+            mint.as_ref(),
+            &amount.to_le_bytes(),
+        ])
+        .to_bytes()
+    }
+}
+
+/// Ledger entry of a mint held by a locker in the shared pool.
+/// Seeds: `[b"balance", locker, mint]`.
+#[account]
+#[derive(Default)]
+pub struct Balance {
+    pub locker: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub lock_until: i64,
+    /// Hour of the latest entry of `withdrawn_hourly`.
+    pub withdrawn_hour: i64,
+    /// Ring of hourly withdrawal totals, indexed by `hour % DAILY_CAP_HOURS`.
+    pub withdrawn_hourly: [u64; Balance::DAILY_CAP_HOURS],
+    pub bump: u8,
+    /// Part of `amount` borrowed through a `Rental`, it must stay in this locker.
+    pub rented: u64,
+    /// Holdings predating the ledger were credited by `backfill_balances`.
+    pub backfilled: bool,
+}
+
+impl Balance {
+    pub const SECONDS_PER_HOUR: i64 = 3_600;
+    /// The current hour and the 24 before it, so that any 24 hours span stays
+    /// under the cap.
+    pub const DAILY_CAP_HOURS: usize = 25;
+    pub const SIZE: usize = 8 + // Discriminator
+    32 + // Locker
+    32 + // Mint
+    8 + // Amount
+    8 + // Lock until
+    8 + // Withdrawn hour
+    8 * Self::DAILY_CAP_HOURS + // Withdrawn hourly
+    1 + // Bump
+    8 + // Rented
+    1; // Backfilled
+
+    /// Clears the hours that left the rolling window since the latest entry.
+    fn roll_daily_window(&mut self, now: i64) {
+        let hour = now / Self::SECONDS_PER_HOUR;
+        let elapsed = hour.saturating_sub(self.withdrawn_hour);
+        if elapsed >= Self::DAILY_CAP_HOURS as i64 {
+            self.withdrawn_hourly = [0; Self::DAILY_CAP_HOURS];
+        } else {
+            for h in (self.withdrawn_hour + 1)..=hour {
+                self.withdrawn_hourly[h as usize % Self::DAILY_CAP_HOURS] = 0;
+            }
+        }
+        self.withdrawn_hour = self.withdrawn_hour.max(hour);
+    }
+
+    /// Withdrawals are summed over a rolling window, not per calendar day.
+    pub fn record_daily_withdrawal(&mut self, amount: u64, now: i64, cap: u64) -> Result<()> {
+        self.roll_daily_window(now);
+        let withdrawn = self
+            .withdrawn_hourly
+            .iter()
+            .fold(amount, |total, hourly| total.saturating_add(*hourly));
+        if cap > 0 && withdrawn > cap {
+            return Err(error!(ErrorCode::LockerDailyCapExceeded));
+        }
+        let slot = &mut self.withdrawn_hourly[self.withdrawn_hour as usize % Self::DAILY_CAP_HOURS];
+        *slot = slot.saturating_add(amount);
+        Ok(())
+    }
+
+    /// Gives back the cap used by a withdrawal recorded at `recorded_at`, if it
+    /// is still inside the rolling window.
+    pub fn revert_daily_withdrawal(&mut self, amount: u64, recorded_at: i64, now: i64) {
+        self.roll_daily_window(now);
+        let hour = recorded_at / Self::SECONDS_PER_HOUR;
+        if self.withdrawn_hour - hour >= Self::DAILY_CAP_HOURS as i64 {
+            return;
+        }
+        let slot = &mut self.withdrawn_hourly[hour as usize % Self::DAILY_CAP_HOURS];
+        *slot = slot.saturating_sub(amount);
+    }
+
+    pub fn check_unlocked(&self, clock: &Clock) -> Result<()> {
+        if clock.unix_timestamp < self.lock_until {
+            return Err(error!(ErrorCode::BalanceLocked));
+        }
+        Ok(())
+    }
+
+    pub fn credit(&mut self, amount: u64) -> Result<()> {
+        self.amount = self
+            .amount
+            .checked_add(amount)
+            .ok_or_else(|| error!(ErrorCode::InsufficientLockerBalance))?;
+        Ok(())
+    }
+
+    pub fn debit(&mut self, amount: u64) -> Result<()> {
+        self.amount = self
+            .amount
+            .checked_sub(amount)
+            .ok_or_else(|| error!(ErrorCode::InsufficientLockerBalance))?;
+        Ok(())
+    }
+}
+
+/// Per-mint withdrawal settings. Seeds: `[b"mint_policy", mint]`.
+#[account]
+#[derive(Default)]
+pub struct MintPolicy {
+    pub mint: Pubkey,
+    /// Window length in slots, 0 disables the rate limit.
+    pub window_slots: u64,
+    pub window_cap: u64,
+    /// Bucket of the latest entry of `withdrawn_buckets`.
+    pub withdrawn_bucket: u64,
+    /// Ring of withdrawal totals per `bucket_slots`, indexed by
+    /// `bucket % WINDOW_BUCKETS`.
+    pub withdrawn_buckets: [u64; MintPolicy::WINDOW_BUCKETS],
+    /// Overrides `Config::locker_daily_cap` for this mint when non-zero.
+    pub locker_daily_cap: u64,
+    /// Seconds between `request_withdraw` and `finalize_withdraw`. When non-zero
+    /// the mint can only be withdrawn through the two-phase flow.
+    pub withdraw_delay: i64,
+    /// Max supply of a program minted currency, 0 for no cap.
+    pub supply_cap: u64,
+    pub bump: u8,
+    /// Overrides `Config::withdraw_fee` for this mint when non-zero.
+    pub withdraw_fee: u64,
+    /// Share of the withdrawn amount kept by the treasury, rounded down so
+    /// single items never pay it.
+    pub withdraw_fee_bps: u16,
+    /// Looser rate limit queued by `set_withdraw_rate_limit`.
+    pub pending_window_slots: u64,
+    pub pending_window_cap: u64,
+    /// When the queued rate limit can be applied, 0 if none is queued.
+    pub pending_available_at: i64,
+}
+
+impl MintPolicy {
+    /// The current bucket and the 24 before it, so that any `window_slots` span
+    /// stays under the cap.
+    pub const WINDOW_BUCKETS: usize = 25;
+    /// Seconds a looser rate limit waits before it can be applied.
+    pub const RATE_LIMIT_DELAY: i64 = 24 * 3_600;
+    pub const SIZE: usize = 8 + // Discriminator
+    32 + // Mint
+    8 + // Window slots
+    8 + // Window cap
+    8 + // Withdrawn bucket
+    8 * Self::WINDOW_BUCKETS + // Withdrawn buckets
+    8 + // Locker daily cap
+    8 + // Withdraw delay
+    8 + // Supply cap
+    1 + // Bump
+    8 + // Withdraw fee
+    2 + // Withdraw fee bps
+    8 + // Pending window slots
+    8 + // Pending window cap
+    8; // Pending available at
+
+    pub const MAX_FEE_BPS: u16 = 1_000;
+
+    /// Lamports and token amount charged on a withdrawal of `amount`.
+    pub fn withdraw_fees(
+        mint_policy: Option<&MintPolicy>,
+        config: &Config,
+        amount: u64,
+    ) -> (u64, u64) {
+        match mint_policy {
+            Some(policy) => {
+                let lamports = if policy.withdraw_fee > 0 {
+                    policy.withdraw_fee
+                } else {
+                    config.withdraw_fee
+                };
+                let fee_amount = (amount as u128 * policy.withdraw_fee_bps as u128 / 10_000) as u64;
+                (lamports, fee_amount)
+            }
+            None => (config.withdraw_fee, 0),
+        }
+    }
+
+    fn bucket_slots(&self) -> u64 {
+        self.window_slots
+            .div_ceil(Self::WINDOW_BUCKETS as u64 - 1)
+            .max(1)
+    }
+
+    /// Clears the buckets that left the rolling window since the latest entry.
+    fn roll_window(&mut self, slot: u64) {
+        let bucket = slot / self.bucket_slots();
+        let elapsed = bucket.saturating_sub(self.withdrawn_bucket);
+        if elapsed >= Self::WINDOW_BUCKETS as u64 {
+            self.withdrawn_buckets = [0; Self::WINDOW_BUCKETS];
+        } else {
+            for b in (self.withdrawn_bucket + 1)..=bucket {
+                self.withdrawn_buckets[b as usize % Self::WINDOW_BUCKETS] = 0;
+            }
+        }
+        self.withdrawn_bucket = self.withdrawn_bucket.max(bucket);
+    }
+
+    fn window_withdrawn(&self) -> u64 {
+        self.withdrawn_buckets
+            .iter()
+            .fold(0, |total, withdrawn| total.saturating_add(*withdrawn))
+    }
+
+    /// Withdrawals are summed over a rolling window of `window_slots`.
+    pub fn record_withdrawal(&mut self, amount: u64, slot: u64) -> Result<()> {
+        if self.window_slots == 0 {
+            return Ok(());
+        }
+        self.roll_window(slot);
+        if self.window_withdrawn().saturating_add(amount) > self.window_cap {
+            return Err(error!(ErrorCode::WithdrawRateLimitExceeded));
+        }
+        let bucket =
+            &mut self.withdrawn_buckets[self.withdrawn_bucket as usize % Self::WINDOW_BUCKETS];
+        *bucket = bucket.saturating_add(amount);
+        Ok(())
+    }
+
+    /// A limit is tighter when it is enabled, at least as long and no larger.
+    pub fn is_tighter(&self, window_slots: u64, window_cap: u64) -> bool {
+        window_slots > 0
+            && (self.window_slots == 0
+                || (window_slots >= self.window_slots && window_cap <= self.window_cap))
+    }
+
+    /// What the current window already holds is carried into the new one, so
+    /// reconfiguring never frees the cap early.
+    pub fn apply_rate_limit(&mut self, window_slots: u64, window_cap: u64, slot: u64) {
+        let withdrawn = if self.window_slots > 0 {
+            self.roll_window(slot);
+            self.window_withdrawn()
+        } else {
+            0
+        };
+        self.window_slots = window_slots;
+        self.window_cap = window_cap;
+        self.withdrawn_buckets = [0; Self::WINDOW_BUCKETS];
+        self.withdrawn_bucket = slot / self.bucket_slots();
+        self.withdrawn_buckets[self.withdrawn_bucket as usize % Self::WINDOW_BUCKETS] = withdrawn;
+        self.pending_available_at = 0;
+    }
+}
+
+/// Withdrawal already debited from the ledger, waiting for its delay to elapse.
+/// Seeds: `[b"pending_withdraw", locker, mint]`.
+#[account]
+#[derive(Default)]
+pub struct PendingWithdrawal {
+    pub locker: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub available_at: i64,
+    pub requested_at: i64,
+}
+
+impl PendingWithdrawal {
+    pub const SIZE: usize = 8 + // Discriminator
+    32 + // Locker
+    32 + // Mint
+    8 + // Amount
+    8 + // Available at
+    8; // Requested at
+}
+
+#[event]
+pub struct WithdrawRateLimitEvent {
+    pub mint: Pubkey,
+    pub window_slots: u64,
+    pub window_cap: u64,
+    /// 0 when the rate limit applied at once.
+    pub available_at: i64,
+}
+
+#[event]
+pub struct WithdrawRequestEvent {
+    pub locker: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub available_at: i64,
+}
+
+#[event]
+pub struct WithdrawCancelEvent {
+    pub locker: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub cancelled_by: Pubkey,
+}
+
+/// Rewards released linearly to a locker between `cliff` and `end`.
+/// Seeds: `[b"vesting", locker, mint]`.
+#[account]
+#[derive(Default)]
+pub struct VestingSchedule {
+    pub locker: Pubkey,
+    pub mint: Pubkey,
+    pub start: i64,
+    pub cliff: i64,
+    pub end: i64,
+    pub total: u64,
+    pub withdrawn: u64,
+    pub bump: u8,
+}
+
+impl VestingSchedule {
+    pub const SIZE: usize = 8 + // Discriminator
+    32 + // Locker
+    32 + // Mint
+    8 + // Start
+    8 + // Cliff
+    8 + // End
+    8 + // Total
+    8 + // Withdrawn
+    1; // Bump
+
+    pub fn vested_amount(&self, now: i64) -> u64 {
+        if now < self.cliff {
+            0
+        } else if now >= self.end {
+            self.total
+        } else {
+            let elapsed = (now - self.start) as u128;
+            let duration = (self.end - self.start) as u128;
+            (self.total as u128 * elapsed / duration) as u64
+        }
+    }
+
+    pub fn withdrawable_amount(&self, now: i64) -> u64 {
+        self.vested_amount(now).saturating_sub(self.withdrawn)
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TradeItem {
+    pub mint: Pubkey,
+    pub amount: u64,
+}
+
+/// Ledger entry returned to CPI callers by `get_balances`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BalanceView {
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub lock_until: i64,
+}
+
+impl BalanceView {
+    /// Entries fitting in the 1024 bytes of return data.
+    pub const MAX_PER_CALL: usize = 20;
+}
+
+impl TradeItem {
+    pub const SIZE: usize = 32 + 8;
+}
+
+/// Session key allowed to deposit and/or withdraw for a locker, up to a per-mint
+/// allowance. Seeds: `[b"delegate", locker, delegate]`.
+#[account]
+#[derive(Default)]
+pub struct Delegate {
+    pub locker: Pubkey,
+    pub delegate: Pubkey,
+    /// `Delegate::DEPOSIT` and/or `Delegate::WITHDRAW`.
+    pub operations: u8,
+    /// Amount of each mint the delegate can still move.
+    pub allowances: Vec<TradeItem>,
+    pub expiry: i64,
+}
+
+impl Delegate {
+    pub const DEPOSIT: u8 = 1;
+    pub const WITHDRAW: u8 = 2;
+    pub const MAX_ALLOWANCES: usize = 8;
+    pub const SIZE: usize = 8 + // Discriminator
+    32 + // Locker
+    32 + // Delegate
+    1 + // Operations
+    4 + TradeItem::SIZE * Self::MAX_ALLOWANCES + // Allowances
+    8; // Expiry
+
+    pub fn check(&self, operation: u8) -> Result<()> {
+        if self.operations & operation == 0 || Clock::get()?.unix_timestamp >= self.expiry {
+            return Err(error!(ErrorCode::Unauthorized));
+        }
+        Ok(())
+    }
+
+    pub fn spend(&mut self, mint: &Pubkey, amount: u64) -> Result<()> {
+        let allowance = self
+            .allowances
+            .iter_mut()
+            .find(|allowance| allowance.mint == *mint)
+            .ok_or_else(|| error!(ErrorCode::DelegateAllowanceExceeded))?;
+        allowance.amount = allowance
+            .amount
+            .checked_sub(amount)
+            .ok_or_else(|| error!(ErrorCode::DelegateAllowanceExceeded))?;
+        Ok(())
+    }
+}
+
+/// Key allowed to move a locker to a new wallet once `delay` has passed since it
+/// asked to, unless the owner cancels. Seeds: `[b"recovery", locker]`.
+#[account]
+#[derive(Default)]
+pub struct Recovery {
+    pub locker: Pubkey,
+    pub recovery_key: Pubkey,
+    pub delay: i64,
+    /// Owner of the locker receiving the balances, default while none pending.
+    pub new_owner: Pubkey,
+    pub available_at: i64,
+}
+
+impl Recovery {
+    pub const SIZE: usize = 8 + // Discriminator
+    32 + // Locker
+    32 + // Recovery key
+    8 + // Delay
+    32 + // New owner
+    8; // Available at
+}
+
+#[event]
+pub struct RecoveryInitiatedEvent {
+    pub locker: Pubkey,
+    pub new_owner: Pubkey,
+    pub available_at: i64,
+}
+
+#[event]
+pub struct RecoveryCancelledEvent {
+    pub locker: Pubkey,
+    pub new_owner: Pubkey,
+}
+
+#[event]
+pub struct LockerRecoveredEvent {
+    pub locker: Pubkey,
+    pub new_locker: Pubkey,
+}
+
+/// Items lent from one locker to another until `expiry`. The borrower entry is
+/// locked meanwhile so the items cannot leave the borrower locker.
+/// Seeds: `[b"rental", lender_locker, mint]`.
+#[account]
+#[derive(Default)]
+pub struct Rental {
+    pub lender_locker: Pubkey,
+    pub borrower_locker: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub expiry: i64,
+    /// Lock of the borrower entry before the rental, restored on settle.
+    pub borrower_lock_until: i64,
+}
+
+impl Rental {
+    pub const SIZE: usize = 8 + // Discriminator
+    32 + // Lender locker
+    32 + // Borrower locker
+    32 + // Mint
+    8 + // Amount
+    8 + // Expiry
+    8; // Borrower lock until
+}
+
+#[event]
+pub struct RentalEvent {
+    pub lender_locker: Pubkey,
+    pub borrower_locker: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub expiry: i64,
+    pub settled: bool,
+}
+
+#[event]
+pub struct LegacyVaultMigrationEvent {
+    pub locker: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct RentReclaimEvent {
+    pub rent_recipient: Pubkey,
+    pub closed: u32,
+    pub lamports: u64,
+}
+
+#[event]
+pub struct FeeSweepEvent {
+    pub destination: Pubkey,
+    pub lamports: u64,
+}
+
+/// Members sharing a locker, which is the v2 locker of the guild address.
+/// Seeds: `[b"guild", id, namespace]`.
+#[account]
+#[derive(Default)]
+pub struct Guild {
+    pub id: u64,
+    pub members: Vec<Pubkey>,
+    /// Member signatures needed to withdraw or change the members.
+    pub threshold: u8,
+}
+
+impl Guild {
+    pub const MAX_MEMBERS: usize = 16;
+    pub const SIZE: usize = 8 + // Discriminator
+    8 + // Id
+    4 + 32 * Self::MAX_MEMBERS + // Members
+    1; // Threshold
+
+    pub fn check_members(&self) -> Result<()> {
+        let mut members = self.members.clone();
+        members.sort();
+        members.dedup();
+        if members.len() != self.members.len()
+            || self.members.len() > Self::MAX_MEMBERS
+            || self.threshold == 0
+            || self.threshold as usize > self.members.len()
+        {
+            return Err(error!(ErrorCode::InvalidGuild));
+        }
+        Ok(())
+    }
+
+    /// `approvers` must hold at least `threshold` distinct member signers.
+    pub fn check_approvals(&self, approvers: &[AccountInfo]) -> Result<()> {
+        let mut approved: Vec<Pubkey> = Vec::new();
+        for approver in approvers {
+            if !approver.is_signer || !self.members.contains(approver.key) {
+                return Err(error!(ErrorCode::Unauthorized));
+            }
+            if !approved.contains(approver.key) {
+                approved.push(approver.key());
+            }
+        }
+        if approved.len() < self.threshold as usize {
+            return Err(error!(ErrorCode::GuildThresholdNotMet));
+        }
+        Ok(())
+    }
+}
+
+/// Trade terms signed by the maker, settled against locker balances when a
+/// taker accepts them. Seeds: `[b"offer", maker_locker, id]`.
+#[account]
+#[derive(Default)]
+pub struct Offer {
+    pub maker_locker: Pubkey,
+    pub taker_locker: Option<Pubkey>,
+    pub id: u64,
+    pub give: Vec<TradeItem>,
+    pub want: Vec<TradeItem>,
+    pub expiry: i64,
+}
+
+impl Offer {
+    pub const MAX_ITEMS: usize = 8;
+    pub const SIZE: usize = 8 + // Discriminator
+    32 + // Maker locker
+    1 + 32 + // Taker locker
+    8 + // Id
+    4 + TradeItem::SIZE * Self::MAX_ITEMS + // Give
+    4 + TradeItem::SIZE * Self::MAX_ITEMS + // Want
+    8; // Expiry
+}
+
+#[event]
+pub struct LockOverrideEvent {
+    pub locker: Pubkey,
+    pub mint: Pubkey,
+    pub lock_until: i64,
+}
+
+#[event]
+pub struct ClawbackEvent {
+    pub locker: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub quarantine: Pubkey,
+    pub reason: u16,
+}
+
+#[event]
+pub struct BurnEvent {
+    pub locker: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    /// Locker owner when it co-signed the burn, `None` for admin only burns.
+    pub co_signer: Option<Pubkey>,
+}
+
+#[event]
+pub struct LockerCreditEvent {
+    pub locker: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct LockerTransferEvent {
+    pub from_locker: Pubkey,
+    pub to_locker: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+}
+
 impl Locker {
     pub const MAX_ENTRIES: usize = 0; // Assuming N is defined somewhere
     pub const MAX_SIZE: usize = 8 + // Discriminator
@@ -236,6 +1997,106 @@ impl Locker {
     8 * Self::MAX_ENTRIES + // Amounts
     1 + // Version
     8; // Space
+    /// v3 lockers append a `LockerV3` to the v2 layout, so both load as `Locker`.
+    pub const V3_SIZE: usize = Self::MAX_SIZE + LockerV3::SIZE;
+    pub const V3: u8 = 3;
+
+    /// Index of a v3 locker, `None` for earlier versions.
+    pub fn index(info: &AccountInfo) -> Option<u16> {
+        let data = info.try_borrow_data().ok()?;
+        let locker = Locker::try_deserialize(&mut &data[..]).ok()?;
+        if locker.version != Self::V3 {
+            return None;
+        }
+        let v3 = LockerV3::deserialize(&mut &data[Self::MAX_SIZE..]).ok()?;
+        Some(v3.index)
+    }
+
+    /// Offset of the `LockerCap`, after the serialized locker and the v3 fields.
+    pub fn cap_offset(&self) -> usize {
+        Self::MAX_SIZE + 32 * self.mints.len() + 8 * self.amounts.len() + LockerV3::SIZE
+    }
+
+    /// Size holding a `LockerCap`, never shrinking the locker.
+    pub fn cap_size(&self, info: &AccountInfo) -> usize {
+        (self.cap_offset() + LockerCap::SIZE).max(info.data_len())
+    }
+}
+
+/// Fields of v3 lockers, stored right after the v2 layout.
+#[derive(AnchorSerialize, AnchorDeserialize, Default)]
+pub struct LockerV3 {
+    pub index: u16,
+}
+
+impl LockerV3 {
+    pub const SIZE: usize = 2; // Index
+
+    pub fn store(&self, info: &AccountInfo) -> Result<()> {
+        self.serialize(&mut &mut info.try_borrow_mut_data()?[Locker::MAX_SIZE..])?;
+        Ok(())
+    }
+}
+
+/// Admin-raised daily cap of every mint of a locker, e.g. for VIP players.
+/// Written by `raise_locker_daily_cap` after the locker fields of any version.
+#[derive(AnchorSerialize, AnchorDeserialize, Default)]
+pub struct LockerCap {
+    pub daily_cap: u64,
+}
+
+impl LockerCap {
+    pub const SIZE: usize = 8; // Daily cap
+
+    pub fn load(locker: &Account<Locker>) -> Result<Self> {
+        let info = locker.to_account_info();
+        let data = info.try_borrow_data()?;
+        Ok(data
+            .get(locker.cap_offset()..)
+            .and_then(|mut cap| Self::deserialize(&mut cap).ok())
+            .unwrap_or_default())
+    }
+
+    pub fn store(&self, locker: &Account<Locker>) -> Result<()> {
+        let info = locker.to_account_info();
+        self.serialize(&mut &mut info.try_borrow_mut_data()?[locker.cap_offset()..])?;
+        Ok(())
+    }
+
+    /// The raised cap wins over the mint and config caps, 0 to use them.
+    pub fn daily_cap(&self, config: &Config, mint_policy: Option<&MintPolicy>) -> u64 {
+        if self.daily_cap > 0 {
+            return self.daily_cap;
+        }
+        match mint_policy {
+            Some(mint_policy) if mint_policy.locker_daily_cap > 0 => mint_policy.locker_daily_cap,
+            _ => config.locker_daily_cap,
+        }
+    }
+}
+
+/// Withdrawal authorized off-chain by the admin. The admin signs `message()` with
+/// the Ed25519 precompile in the instruction right before `redeem_voucher`.
+pub struct Voucher {
+    pub locker: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub nonce: u64,
+    pub expiry: i64,
+}
+
+impl Voucher {
+    pub const MESSAGE_SIZE: usize = 32 + 32 + 8 + 8 + 8;
+
+    pub fn message(&self) -> Vec<u8> {
+        let mut message = Vec::with_capacity(Self::MESSAGE_SIZE);
+        message.extend_from_slice(self.locker.as_ref());
+        message.extend_from_slice(self.mint.as_ref());
+        message.extend_from_slice(&self.amount.to_le_bytes());
+        message.extend_from_slice(&self.nonce.to_le_bytes());
+        message.extend_from_slice(&self.expiry.to_le_bytes());
+        message
+    }
 }
 
 #[error_code]
@@ -264,6 +2125,105 @@ pub enum ErrorCode {
     WrongRemainingAccountsSize,
     #[msg("Transfer failed.")]
     TransferFail,
+    #[msg("Operation expired: valid_until has passed.")]
+    OperationExpired,
+    #[msg("Invalid voucher signature.")]
+    InvalidVoucherSignature,
+    #[msg("Invalid leaf count.")]
+    InvalidLeafCount,
+    #[msg("Entitlement already claimed.")]
+    AlreadyClaimed,
+    #[msg("Invalid merkle proof.")]
+    InvalidMerkleProof,
+    #[msg("Invalid balance account.")]
+    InvalidBalanceAccount,
+    #[msg("Insufficient locker balance.")]
+    InsufficientLockerBalance,
+    #[msg("Source and destination lockers are the same.")]
+    SameLocker,
+    #[msg("Unauthorized.")]
+    Unauthorized,
+    #[msg("Invalid offer.")]
+    InvalidOffer,
+    #[msg("Offer terms mismatch.")]
+    OfferTermsMismatch,
+    #[msg("Balance is locked.")]
+    BalanceLocked,
+    #[msg("Invalid vesting schedule.")]
+    InvalidVestingSchedule,
+    #[msg("Amount exceeds the vested withdrawable amount.")]
+    VestingAmountExceeded,
+    #[msg("Invalid mint policy account.")]
+    InvalidMintPolicyAccount,
+    #[msg("Withdrawal rate limit exceeded for this mint.")]
+    WithdrawRateLimitExceeded,
+    #[msg("Locker daily withdrawal cap exceeded.")]
+    LockerDailyCapExceeded,
+    #[msg("This mint must be withdrawn with request_withdraw.")]
+    DelayedWithdrawalRequired,
+    #[msg("Withdrawal delay has not elapsed.")]
+    WithdrawalDelayNotElapsed,
+    #[msg("Quarantine treasury is not set.")]
+    QuarantineNotSet,
+    #[msg("Mint supply cap exceeded.")]
+    SupplyCapExceeded,
+    #[msg("Locker does not belong to this namespace.")]
+    InvalidLocker,
+    #[msg("Invalid guild members or threshold.")]
+    InvalidGuild,
+    #[msg("Not enough guild members approved.")]
+    GuildThresholdNotMet,
+    #[msg("Delegate allowance exceeded.")]
+    DelegateAllowanceExceeded,
+    #[msg("No recovery pending or its delay has not elapsed.")]
+    RecoveryNotReady,
+    #[msg("Rental has not expired.")]
+    RentalNotExpired,
+    #[msg("Borrower already holds this mint.")]
+    RentalBalanceInUse,
+    #[msg("Too many balances requested.")]
+    TooManyBalances,
+    #[msg("Calling program is not allowed.")]
+    UnauthorizedCaller,
+    #[msg("Invalid CPI guard.")]
+    InvalidCpiGuard,
+    #[msg("Fee exceeds the maximum.")]
+    InvalidFee,
+    #[msg("Rent recipient is not set.")]
+    RentRecipientNotSet,
+    #[msg("Distribution funds exhausted.")]
+    DistributionExhausted,
+    #[msg("Amount overflow.")]
+    AmountOverflow,
+    #[msg("Balance holds rented items.")]
+    RentedBalance,
+    #[msg("Balance already backfilled.")]
+    BalanceAlreadyBackfilled,
+    #[msg("Balance backfill is closed.")]
+    BackfillClosed,
+    #[msg("No rate limit change pending or its delay has not elapsed.")]
+    RateLimitChangeNotReady,
+    #[msg("Quarantine treasury is already set.")]
+    QuarantineAlreadySet,
+    #[msg("Invalid rental account.")]
+    InvalidRental,
+    #[msg("Balance has items lent out.")]
+    LentBalance,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValidUntil {
+    Slot(u64),
+    Timestamp(i64),
+}
+
+impl ValidUntil {
+    pub fn has_passed(&self, clock: &Clock) -> bool {
+        match *self {
+            ValidUntil::Slot(slot) => clock.slot > slot,
+            ValidUntil::Timestamp(timestamp) => clock.unix_timestamp > timestamp,
+        }
+    }
 }
 
 pub enum WithdrawType {
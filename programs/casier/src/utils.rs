@@ -1,15 +1,21 @@
 use crate::state::{ErrorCode, *};
 use anchor_lang::prelude::*;
 use anchor_lang::solana_program::{
+    ed25519_program,
     entrypoint::ProgramResult,
+    instruction::{get_stack_height, TRANSACTION_LEVEL_STACK_HEIGHT},
+    keccak,
     program::{invoke, invoke_signed},
+    program_option::COption,
     pubkey::Pubkey,
     rent::Rent,
     system_instruction,
+    sysvar::instructions::{load_current_index_checked, load_instruction_at_checked},
 };
 use anchor_spl;
-use anchor_spl::token::TokenAccount;
-use mpl_token_metadata::instructions::TransferV1CpiBuilder;
+use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::token::{Mint, Token, TokenAccount};
+use mpl_token_metadata::instructions::{BurnV1CpiBuilder, TransferV1CpiBuilder};
 
 pub fn create_pda_account<'a>(
     payer: &AccountInfo<'a>,
@@ -117,7 +123,7 @@ pub fn spl_init_token_account(params: InitializeTokenAccountParams<'_, '_>) -> R
         )?,
         &[account, mint, owner, token_program, rent],
     );
-    return result.map_err(|_| ErrorCode::TransferFail.into());
+    result.map_err(|_| ErrorCode::TransferFail.into())
 }
 
 pub struct TokenTransferParams<'a: 'b, 'b> {
@@ -157,10 +163,373 @@ pub fn spl_token_transfer(params: TokenTransferParams<'_, '_>) -> Result<()> {
         &[source, destination, authority, token_program],
     );
 
-    return result.map_err(|_| ErrorCode::TransferFail.into());
+    result.map_err(|_| ErrorCode::TransferFail.into())
 }
 
-fn get_token_account(ai: &AccountInfo) -> Result<TokenAccount> {
+/// Loads the `[b"balance", locker, mint]` ledger entry, creating it on first use.
+pub fn load_or_init_balance<'info>(
+    balance: &AccountInfo<'info>,
+    locker: &Pubkey,
+    mint: &Pubkey,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<Balance> {
+    let (address, bump) = Pubkey::find_program_address(
+        &[b"balance".as_ref(), locker.as_ref(), mint.as_ref()],
+        &crate::ID,
+    );
+    if address != balance.key() {
+        return Err(error!(ErrorCode::InvalidBalanceAccount));
+    }
+    if *balance.owner != crate::ID {
+        create_pda_account(
+            payer,
+            Balance::SIZE,
+            &crate::ID,
+            system_program,
+            balance,
+            &[b"balance".as_ref(), locker.as_ref(), mint.as_ref(), &[bump]],
+        )?;
+        store_balance(
+            balance,
+            &Balance {
+                locker: *locker,
+                mint: *mint,
+                bump,
+                ..Default::default()
+            },
+        )?;
+    }
+    Balance::try_deserialize(&mut &balance.try_borrow_data()?[..])
+}
+
+/// Debits `amount` of `mint` from one locker's ledger and credits it to another.
+pub fn move_balance<'info>(
+    from_balance_ai: &AccountInfo<'info>,
+    to_balance_ai: &AccountInfo<'info>,
+    from_locker: &Pubkey,
+    to_locker: &Pubkey,
+    mint: &Pubkey,
+    amount: u64,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<()> {
+    let mut from_balance =
+        load_or_init_balance(from_balance_ai, from_locker, mint, payer, system_program)?;
+    let mut to_balance =
+        load_or_init_balance(to_balance_ai, to_locker, mint, payer, system_program)?;
+    from_balance.check_unlocked(&Clock::get()?)?;
+    from_balance.debit(amount)?;
+    to_balance.credit(amount)?;
+    store_balance(from_balance_ai, &from_balance)?;
+    store_balance(to_balance_ai, &to_balance)?;
+    emit!(LockerTransferEvent {
+        from_locker: *from_locker,
+        to_locker: *to_locker,
+        mint: *mint,
+        amount,
+    });
+    Ok(())
+}
+
+/// Checks the lock and daily cap of a (locker, mint) entry and debits a
+/// withdrawal from it.
+pub fn debit_withdrawal<'info>(
+    balance_ai: &AccountInfo<'info>,
+    locker: &Account<'info, Locker>,
+    mint: &Pubkey,
+    amount: u64,
+    config: &Config,
+    mint_policy: Option<&MintPolicy>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<()> {
+    let clock = Clock::get()?;
+    let mut balance = load_or_init_balance(balance_ai, &locker.key(), mint, payer, system_program)?;
+    balance.check_unlocked(&clock)?;
+    let daily_cap = LockerCap::load(locker)?.daily_cap(config, mint_policy);
+    balance.record_daily_withdrawal(amount, clock.unix_timestamp, daily_cap)?;
+    balance.debit(amount)?;
+    store_balance(balance_ai, &balance)
+}
+
+/// Counts a withdrawal against the daily cap of the locker without debiting the
+/// ledger, for vested rewards that are not part of it.
+pub fn record_daily_withdrawal<'info>(
+    balance_ai: &AccountInfo<'info>,
+    locker: &Account<'info, Locker>,
+    mint: &Pubkey,
+    amount: u64,
+    config: &Config,
+    mint_policy: Option<&MintPolicy>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<()> {
+    let mut balance = load_or_init_balance(balance_ai, &locker.key(), mint, payer, system_program)?;
+    let daily_cap = LockerCap::load(locker)?.daily_cap(config, mint_policy);
+    balance.record_daily_withdrawal(amount, Clock::get()?.unix_timestamp, daily_cap)?;
+    store_balance(balance_ai, &balance)
+}
+
+pub fn credit_deposit<'info>(
+    balance_ai: &AccountInfo<'info>,
+    locker: &Pubkey,
+    mint: &Pubkey,
+    amount: u64,
+    lock_until: Option<i64>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<()> {
+    let mut balance = load_or_init_balance(balance_ai, locker, mint, payer, system_program)?;
+    balance.credit(amount)?;
+    if let Some(lock_until) = lock_until {
+        balance.lock_until = balance.lock_until.max(lock_until);
+    }
+    store_balance(balance_ai, &balance)
+}
+
+/// Mints whose authority is this PDA are soft currencies: withdrawals mint and
+/// deposits burn instead of going through the pool.
+pub fn mint_authority(namespace_seed: &[u8]) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"mint_authority".as_ref(), namespace_seed], &crate::ID)
+}
+
+/// Collects the lamport and token fees of a namespace.
+pub fn treasury(namespace_seed: &[u8]) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"treasury".as_ref(), namespace_seed], &crate::ID)
+}
+
+pub fn collect_fee<'info>(
+    payer: &AccountInfo<'info>,
+    treasury: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    lamports: u64,
+) -> Result<()> {
+    if lamports == 0 {
+        return Ok(());
+    }
+    // The first fee also funds the treasury up to the rent exempt minimum.
+    let top_up = Rent::get()?
+        .minimum_balance(0)
+        .saturating_sub(treasury.lamports());
+    anchor_lang::system_program::transfer(
+        CpiContext::new(
+            system_program.clone(),
+            anchor_lang::system_program::Transfer {
+                from: payer.clone(),
+                to: treasury.clone(),
+            },
+        ),
+        lamports + top_up,
+    )
+}
+
+/// Creates the treasury token account `[b"treasury", mint, namespace]` if needed.
+pub fn init_treasury_ta<'info>(
+    treasury_ta: &AccountInfo<'info>,
+    treasury: &AccountInfo<'info>,
+    mint: &AccountInfo<'info>,
+    namespace_seed: &[u8],
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    token_program: &AccountInfo<'info>,
+    rent: &AccountInfo<'info>,
+) -> Result<()> {
+    let (address, bump) = Pubkey::find_program_address(
+        &[b"treasury".as_ref(), mint.key.as_ref(), namespace_seed],
+        &crate::ID,
+    );
+    if address != treasury_ta.key() {
+        return Err(error!(ErrorCode::InvalidVault));
+    }
+    if *treasury_ta.owner == token_program.key() {
+        return Ok(());
+    }
+    spl_init_token_account(InitializeTokenAccountParams {
+        account: treasury_ta.clone(),
+        account_signer_seeds: &[
+            b"treasury".as_ref(),
+            mint.key.as_ref(),
+            namespace_seed,
+            &[bump],
+        ],
+        mint: mint.clone(),
+        owner: treasury.clone(),
+        payer: payer.clone(),
+        system_program: system_program.clone(),
+        token_program: token_program.clone(),
+        rent: rent.clone(),
+    })
+}
+
+/// Creates the pool token account `[mint, namespace]` if needed.
+pub fn init_pool_ta<'info>(
+    burn_ta: &AccountInfo<'info>,
+    mint: &AccountInfo<'info>,
+    namespace_seed: &[u8],
+    burn_bump: u8,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    token_program: &AccountInfo<'info>,
+    rent: &AccountInfo<'info>,
+) -> Result<()> {
+    let address = Pubkey::create_program_address(
+        &[mint.key.as_ref(), namespace_seed, &[burn_bump]],
+        &crate::ID,
+    )
+    .map_err(|_| error!(ErrorCode::InvalidVault))?;
+    if address != burn_ta.key() {
+        return Err(error!(ErrorCode::InvalidVault));
+    }
+    if *burn_ta.owner == token_program.key() {
+        return Ok(());
+    }
+    spl_init_token_account(InitializeTokenAccountParams {
+        account: burn_ta.clone(),
+        account_signer_seeds: &[mint.key.as_ref(), namespace_seed, &[burn_bump]],
+        mint: mint.clone(),
+        owner: burn_ta.clone(),
+        payer: payer.clone(),
+        system_program: system_program.clone(),
+        token_program: token_program.clone(),
+        rent: rent.clone(),
+    })
+}
+
+pub fn get_program_minted(mint: &AccountInfo, namespace_seed: &[u8]) -> Result<Option<Mint>> {
+    let mint_data = Mint::try_deserialize(&mut &mint.try_borrow_data()?[..])?;
+    let (authority, _) = mint_authority(namespace_seed);
+    Ok(match mint_data.mint_authority {
+        COption::Some(mint_authority) if mint_authority == authority => Some(mint_data),
+        _ => None,
+    })
+}
+
+pub fn store_balance(ai: &AccountInfo, balance: &Balance) -> Result<()> {
+    balance.try_serialize(&mut &mut ai.try_borrow_mut_data()?[..])
+}
+
+/// Loads the `[b"mint_policy", mint]` account, `None` if the mint has no policy.
+pub fn load_mint_policy(
+    ai: &AccountInfo,
+    mint: &Pubkey,
+    namespace_seed: &[u8],
+) -> Result<Option<MintPolicy>> {
+    let (address, _) = Pubkey::find_program_address(
+        &[b"mint_policy".as_ref(), mint.as_ref(), namespace_seed],
+        &crate::ID,
+    );
+    if address != ai.key() {
+        return Err(error!(ErrorCode::InvalidMintPolicyAccount));
+    }
+    if *ai.owner != crate::ID {
+        return Ok(None);
+    }
+    Ok(Some(MintPolicy::try_deserialize(
+        &mut &ai.try_borrow_data()?[..],
+    )?))
+}
+
+pub fn store_mint_policy(ai: &AccountInfo, mint_policy: &MintPolicy) -> Result<()> {
+    mint_policy.try_serialize(&mut &mut ai.try_borrow_mut_data()?[..])
+}
+
+pub fn check_valid_until(valid_until: Option<ValidUntil>) -> Result<()> {
+    if let Some(valid_until) = valid_until {
+        if valid_until.has_passed(&Clock::get()?) {
+            return Err(error!(ErrorCode::OperationExpired));
+        }
+    }
+    Ok(())
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16> {
+    data.get(offset..offset + 2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .ok_or_else(|| error!(ErrorCode::InvalidVoucherSignature))
+}
+
+/// Rejects CPI from programs outside the config allowlist, or any CPI while CPI
+/// is disabled. The allowlist only sees the program of the top level
+/// instruction, not the direct caller: an allowed program may itself be
+/// invoked by, or invoke, programs that are not allowed.
+pub fn check_cpi_caller(config: &Config, instructions: &AccountInfo) -> Result<()> {
+    if config.cpi_guard == Config::CPI_OPEN || get_stack_height() == TRANSACTION_LEVEL_STACK_HEIGHT
+    {
+        return Ok(());
+    }
+    if config.cpi_guard == Config::CPI_ALLOWLIST {
+        let current_index = load_current_index_checked(instructions)? as usize;
+        let top_level = load_instruction_at_checked(current_index, instructions)?.program_id;
+        if config.cpi_allowlist.contains(&top_level) {
+            return Ok(());
+        }
+    }
+    Err(error!(ErrorCode::UnauthorizedCaller))
+}
+
+/// Checks that the instruction preceding the current one is an Ed25519 precompile
+/// instruction verifying a single signature of `message` by `signer`.
+pub fn verify_ed25519_instruction(
+    instructions: &AccountInfo,
+    signer: &Pubkey,
+    message: &[u8],
+) -> Result<()> {
+    let current_index = load_current_index_checked(instructions)? as usize;
+    if current_index == 0 {
+        return Err(error!(ErrorCode::InvalidVoucherSignature));
+    }
+    let ix = load_instruction_at_checked(current_index - 1, instructions)?;
+    if ix.program_id != ed25519_program::ID || !ix.accounts.is_empty() {
+        return Err(error!(ErrorCode::InvalidVoucherSignature));
+    }
+
+    // Layout: num_signatures (u8), padding (u8), then one Ed25519SignatureOffsets.
+    let data = &ix.data;
+    if data.len() < 16 || data[0] != 1 {
+        return Err(error!(ErrorCode::InvalidVoucherSignature));
+    }
+    let signature_ix_index = read_u16(data, 4)?;
+    let public_key_offset = read_u16(data, 6)? as usize;
+    let public_key_ix_index = read_u16(data, 8)?;
+    let message_offset = read_u16(data, 10)? as usize;
+    let message_size = read_u16(data, 12)? as usize;
+    let message_ix_index = read_u16(data, 14)?;
+
+    // All offsets must point into the Ed25519 instruction itself.
+    if signature_ix_index != u16::MAX
+        || public_key_ix_index != u16::MAX
+        || message_ix_index != u16::MAX
+    {
+        return Err(error!(ErrorCode::InvalidVoucherSignature));
+    }
+    let public_key = data
+        .get(public_key_offset..public_key_offset + 32)
+        .ok_or_else(|| error!(ErrorCode::InvalidVoucherSignature))?;
+    let signed_message = data
+        .get(message_offset..message_offset + message_size)
+        .ok_or_else(|| error!(ErrorCode::InvalidVoucherSignature))?;
+    if public_key != signer.as_ref() || signed_message != message {
+        return Err(error!(ErrorCode::InvalidVoucherSignature));
+    }
+    Ok(())
+}
+
+/// Nodes are `keccak(0x01 || min(a, b) || max(a, b))`, so proofs don't carry
+/// left/right flags.
+pub fn verify_merkle_proof(proof: &[[u8; 32]], root: &[u8; 32], leaf: [u8; 32]) -> bool {
+    let computed = proof.iter().fold(leaf, |node, sibling| {
+        let (left, right) = if node <= *sibling {
+            (node, *sibling)
+        } else {
+            (*sibling, node)
+        };
+        keccak::hashv(&[&[1u8], &left, &right]).to_bytes()
+    });
+    computed == *root
+}
+
+pub fn get_token_account(ai: &AccountInfo) -> Result<TokenAccount> {
     TokenAccount::try_deserialize(&mut &ai.data.borrow()[..])
 }
 
@@ -169,24 +538,45 @@ pub fn perform_deposit_v2<'b, 'c, 'info>(
     vault_bump: u8,
     deposit_amount: u64,
     burn_bump: u8,
+    lock_until: Option<i64>,
 ) -> Result<()> {
+    let namespace_seed = pd.config.namespace_seed();
+    if get_program_minted(pd.mint, &namespace_seed)?.is_some() {
+        anchor_spl::token::burn(
+            CpiContext::new(
+                pd.token_program.to_account_info(),
+                anchor_spl::token::Burn {
+                    mint: pd.mint.to_account_info(),
+                    from: pd.user_ta.to_account_info(),
+                    authority: pd.owner.to_account_info(),
+                },
+            ),
+            deposit_amount,
+        )?;
+        return credit_deposit(
+            pd.balance,
+            &pd.locker.key(),
+            &pd.mint.key(),
+            deposit_amount,
+            lock_until,
+            pd.owner,
+            pd.system_program,
+        );
+    }
+
     let should_go_in_burn_ta = true;
     if should_go_in_burn_ta {
-        if *pd.burn_ta.to_account_info().owner != pd.token_program.key() {
-            let vault_account_seeds = &[pd.mint.to_account_info().key.as_ref(), &[burn_bump]];
-            let vault_account_signer = &vault_account_seeds[..];
-            // initialize nft vault account
-            spl_init_token_account(InitializeTokenAccountParams {
-                account: pd.burn_ta.to_account_info(),
-                account_signer_seeds: vault_account_signer,
-                mint: pd.mint.to_account_info(),
-                owner: pd.burn_ta.to_account_info(),
-                payer: pd.owner.to_account_info(),
-                system_program: pd.system_program.to_account_info(),
-                token_program: pd.token_program.to_account_info(),
-                rent: pd.rent.to_account_info(),
-            })?;
-        }
+        // The ledger is credited for whatever lands here, so only the pool will do.
+        init_pool_ta(
+            pd.burn_ta,
+            pd.mint,
+            &namespace_seed,
+            burn_bump,
+            pd.owner,
+            pd.system_program,
+            pd.token_program,
+            &pd.rent.to_account_info(),
+        )?;
     } else {
         if *pd.vault_ta.to_account_info().owner != pd.token_program.key() {
             let vault_account_seeds = &[
@@ -211,10 +601,10 @@ pub fn perform_deposit_v2<'b, 'c, 'info>(
     }
 
     let (dest_ta, dest_ai) = match should_go_in_burn_ta {
-        true => (get_token_account(&pd.burn_ta)?, pd.burn_ta),
-        false => (get_token_account(&pd.vault_ta)?, pd.vault_ta),
+        true => (get_token_account(pd.burn_ta)?, pd.burn_ta),
+        false => (get_token_account(pd.vault_ta)?, pd.vault_ta),
     };
-    let is_valid_dest = dest_ta.owner == dest_ta.owner && dest_ta.mint == pd.mint.key();
+    let is_valid_dest = dest_ta.owner == dest_ai.key() && dest_ta.mint == pd.mint.key();
 
     if !is_valid_dest {
         return Err(error!(ErrorCode::InvalidVault));
@@ -243,60 +633,112 @@ pub fn perform_deposit_v2<'b, 'c, 'info>(
         spl_token_transfer(TokenTransferParams {
             source: pd.user_ta.to_account_info(),
             destination: dest_ai.clone(),
-            amount: deposit_amount.into(),
+            amount: deposit_amount,
             authority: pd.owner.to_account_info(),
             authority_signer_seeds: &[],
             token_program: pd.token_program.to_account_info(),
         })?;
     }
 
+    credit_deposit(
+        pd.balance,
+        &pd.locker.key(),
+        &pd.mint.key(),
+        deposit_amount,
+        lock_until,
+        pd.owner,
+        pd.system_program,
+    )?;
+
     let vault_lamports = **pd.vault_ta.try_borrow_mut_lamports()?;
-    if should_go_in_burn_ta && vault_lamports > 0 {
-        let vault_ta = get_token_account(pd.vault_ta)?;
-        if vault_ta.amount > 0 {
-            anchor_spl::token::transfer(
-                CpiContext::new_with_signer(
-                    pd.token_program.to_account_info(),
-                    anchor_spl::token::Transfer {
-                        from: pd.vault_ta.to_account_info(),
-                        to: dest_ai.clone(),
-                        authority: pd.vault_ta.to_account_info(),
-                    },
-                    &[&[
-                        pd.mint.key().as_ref(),
-                        pd.locker.owner.key().as_ref(),
-                        &[vault_bump],
-                    ]],
-                ),
-                vault_ta.amount.into(),
-            )?;
-            anchor_spl::token::close_account(CpiContext::new_with_signer(
-                pd.token_program.to_account_info(),
-                anchor_spl::token::CloseAccount {
-                    account: pd.vault_ta.to_account_info(),
-                    destination: pd.owner.to_account_info(),
-                    authority: pd.vault_ta.to_account_info(),
-                },
-                &[&[
-                    pd.mint.key().as_ref(),
-                    pd.locker.owner.key().as_ref(),
-                    &[vault_bump],
-                ]],
-            ))?;
-        }
+    // Legacy vaults predate namespaces and only migrate into namespace 0 pools.
+    if should_go_in_burn_ta
+        && vault_lamports > 0
+        && pd.config.namespace == 0
+        && get_token_account(pd.vault_ta)?.amount > 0
+    {
+        let amount = drain_legacy_vault(
+            pd.mint,
+            pd.vault_ta,
+            dest_ai,
+            &pd.locker.owner,
+            vault_bump,
+            pd.locker_owner,
+            pd.token_program,
+        )?;
+        credit_deposit(
+            pd.balance,
+            &pd.locker.key(),
+            &pd.mint.key(),
+            amount,
+            None,
+            pd.owner,
+            pd.system_program,
+        )?;
+        emit!(LegacyVaultMigrationEvent {
+            locker: pd.locker.key(),
+            mint: pd.mint.key(),
+            amount,
+        });
     }
 
     Ok(())
 }
 
+/// Moves a legacy `[mint, owner]` vault into the pool and closes it, sending
+/// its rent to `rent_destination`. Returns the amount moved.
+pub fn drain_legacy_vault<'info>(
+    mint: &AccountInfo<'info>,
+    vault_ta: &AccountInfo<'info>,
+    burn_ta: &AccountInfo<'info>,
+    vault_owner: &Pubkey,
+    vault_bump: u8,
+    rent_destination: &AccountInfo<'info>,
+    token_program: &AccountInfo<'info>,
+) -> Result<u64> {
+    let amount = get_token_account(vault_ta)?.amount;
+    let vault_signer: &[&[&[u8]]] = &[&[mint.key.as_ref(), vault_owner.as_ref(), &[vault_bump]]];
+    if amount > 0 {
+        anchor_spl::token::transfer(
+            CpiContext::new_with_signer(
+                token_program.clone(),
+                anchor_spl::token::Transfer {
+                    from: vault_ta.clone(),
+                    to: burn_ta.clone(),
+                    authority: vault_ta.clone(),
+                },
+                vault_signer,
+            ),
+            amount,
+        )?;
+    }
+    anchor_spl::token::close_account(CpiContext::new_with_signer(
+        token_program.clone(),
+        anchor_spl::token::CloseAccount {
+            account: vault_ta.clone(),
+            destination: rent_destination.clone(),
+            authority: vault_ta.clone(),
+        },
+        vault_signer,
+    ))?;
+    Ok(amount)
+}
+
 pub fn perform_withdraw_v2<'b, 'c, 'info>(
     pd: PerformWithdrawV2<'b, 'c, 'info>,
     withdraw_amount: u64,
     vault_bump: u8,
     burn_bump: u8,
 ) -> Result<()> {
-    if *pd.burn_ta.owner != pd.token_program.key() {
-        let vault_account_seeds = &[pd.mint.to_account_info().key.as_ref(), &[burn_bump]];
+    let namespace_seed = pd.config.namespace_seed();
+    let legacy_vault = pd.config.namespace == 0;
+    let program_minted = get_program_minted(pd.mint, &namespace_seed)?;
+    if program_minted.is_none() && *pd.burn_ta.owner != pd.token_program.key() {
+        let vault_account_seeds = &[
+            pd.mint.to_account_info().key.as_ref(),
+            &namespace_seed,
+            &[burn_bump],
+        ];
         let vault_account_signer = &vault_account_seeds[..];
         // initialize nft vault account
         spl_init_token_account(InitializeTokenAccountParams {
@@ -310,7 +752,7 @@ pub fn perform_withdraw_v2<'b, 'c, 'info>(
             rent: pd.rent.to_account_info(),
         })?;
     }
-    if *pd.vault_ta.owner != pd.token_program.key() {
+    if program_minted.is_none() && legacy_vault && *pd.vault_ta.owner != pd.token_program.key() {
         let vault_account_seeds = &[
             pd.mint.to_account_info().key.as_ref(),
             pd.vault_ta_owner.key.as_ref(),
@@ -335,7 +777,7 @@ pub fn perform_withdraw_v2<'b, 'c, 'info>(
         let cpi_accounts = anchor_spl::associated_token::Create {
             payer: pd.user_ta_owner.to_account_info(),
             associated_token: pd.user_ta.to_account_info(),
-            authority: pd.user_ta_owner.to_account_info(),
+            authority: pd.recipient.to_account_info(),
             mint: pd.mint.to_account_info(),
             system_program: pd.system_program.to_account_info(),
             token_program: pd.token_program.to_account_info(),
@@ -343,9 +785,154 @@ pub fn perform_withdraw_v2<'b, 'c, 'info>(
         let cpi_ctx = anchor_lang::context::CpiContext::new(cpi_program, cpi_accounts);
         anchor_spl::associated_token::create(cpi_ctx)?;
     }
+    let user_ta = get_token_account(pd.user_ta)?;
+    if user_ta.owner != pd.recipient.key() || user_ta.mint != pd.mint.key() {
+        return Err(error!(ErrorCode::InvalidVault));
+    }
 
-    let vault_ta_data = get_token_account(pd.vault_ta)?;
-    let vault_ta_amount = vault_ta_data.amount;
+    let clock = Clock::get()?;
+    let mut mint_policy = load_mint_policy(pd.mint_policy, &pd.mint.key(), &namespace_seed)?;
+
+    if !pd.delayed && matches!(&mint_policy, Some(policy) if policy.withdraw_delay > 0) {
+        return Err(error!(ErrorCode::DelayedWithdrawalRequired));
+    }
+
+    if let Some(balance_ai) = pd.balance {
+        // Legacy vault holdings predate the ledger, they move into the pool and
+        // are credited so that owners never migrated can still withdraw them.
+        let vault_amount = if legacy_vault
+            && program_minted.is_none()
+            && pd.vault_ta_owner.key() == pd.locker.owner
+        {
+            get_token_account(pd.vault_ta)?.amount
+        } else {
+            0
+        };
+        if vault_amount > 0 {
+            anchor_spl::token::transfer(
+                CpiContext::new_with_signer(
+                    pd.token_program.to_account_info(),
+                    anchor_spl::token::Transfer {
+                        from: pd.vault_ta.to_account_info(),
+                        to: pd.burn_ta.to_account_info(),
+                        authority: pd.vault_ta.to_account_info(),
+                    },
+                    &[&[
+                        pd.mint.key().as_ref(),
+                        pd.locker.owner.as_ref(),
+                        &[vault_bump],
+                    ]],
+                ),
+                vault_amount,
+            )?;
+            credit_deposit(
+                balance_ai,
+                &pd.locker.key(),
+                &pd.mint.key(),
+                vault_amount,
+                None,
+                pd.user_ta_owner,
+                pd.system_program,
+            )?;
+            emit!(LegacyVaultMigrationEvent {
+                locker: pd.locker.key(),
+                mint: pd.mint.key(),
+                amount: vault_amount,
+            });
+        }
+        debit_withdrawal(
+            balance_ai,
+            pd.locker,
+            &pd.mint.key(),
+            withdraw_amount,
+            pd.config,
+            mint_policy.as_ref(),
+            pd.user_ta_owner,
+            pd.system_program,
+        )?;
+    }
+
+    if let Some(mint_policy) = mint_policy.as_mut() {
+        mint_policy.record_withdrawal(withdraw_amount, clock.slot)?;
+        store_mint_policy(pd.mint_policy, mint_policy)?;
+    }
+
+    let mut fee_amount = 0;
+    let (fee_lamports, fee) =
+        MintPolicy::withdraw_fees(mint_policy.as_ref(), pd.config, withdraw_amount);
+    collect_fee(
+        pd.user_ta_owner,
+        pd.treasury,
+        pd.system_program,
+        fee_lamports,
+    )?;
+    if fee > 0 && pd.token_record.is_none() {
+        init_treasury_ta(
+            pd.treasury_ta,
+            pd.treasury,
+            pd.mint,
+            &namespace_seed,
+            pd.user_ta_owner,
+            pd.system_program,
+            pd.token_program,
+            &pd.rent.to_account_info(),
+        )?;
+        fee_amount = fee;
+    }
+
+    if let Some(mint_data) = program_minted {
+        if let Some(mint_policy) = mint_policy.as_ref() {
+            let supply = mint_data
+                .supply
+                .checked_add(withdraw_amount)
+                .ok_or_else(|| error!(ErrorCode::SupplyCapExceeded))?;
+            if mint_policy.supply_cap > 0 && supply > mint_policy.supply_cap {
+                return Err(error!(ErrorCode::SupplyCapExceeded));
+            }
+        }
+        // There is no pool for these mints, the burn_ta slot carries the authority.
+        let (authority, authority_bump) = mint_authority(&namespace_seed);
+        if pd.burn_ta.key() != authority {
+            return Err(error!(ErrorCode::InvalidVault));
+        }
+        let authority_seeds: &[&[u8]] = &[
+            b"mint_authority".as_ref(),
+            &namespace_seed,
+            &[authority_bump],
+        ];
+        if fee_amount > 0 {
+            anchor_spl::token::mint_to(
+                CpiContext::new_with_signer(
+                    pd.token_program.to_account_info(),
+                    anchor_spl::token::MintTo {
+                        mint: pd.mint.to_account_info(),
+                        to: pd.treasury_ta.to_account_info(),
+                        authority: pd.burn_ta.to_account_info(),
+                    },
+                    &[authority_seeds],
+                ),
+                fee_amount,
+            )?;
+        }
+        return anchor_spl::token::mint_to(
+            CpiContext::new_with_signer(
+                pd.token_program.to_account_info(),
+                anchor_spl::token::MintTo {
+                    mint: pd.mint.to_account_info(),
+                    to: pd.user_ta.to_account_info(),
+                    authority: pd.burn_ta.to_account_info(),
+                },
+                &[authority_seeds],
+            ),
+            withdraw_amount - fee_amount,
+        );
+    }
+
+    let vault_ta_amount = if legacy_vault {
+        get_token_account(pd.vault_ta)?.amount
+    } else {
+        0
+    };
     let burn_ta_data = get_token_account(pd.burn_ta)?;
     let burn_ta_amount = burn_ta_data.amount;
     let total_amount = vault_ta_amount + burn_ta_amount;
@@ -370,7 +957,7 @@ pub fn perform_withdraw_v2<'b, 'c, 'info>(
                     &[vault_bump],
                 ]],
             ),
-            vault_ta_amount.into(),
+            vault_ta_amount,
         )?;
         close_vault_ta = true;
     }
@@ -381,7 +968,7 @@ pub fn perform_withdraw_v2<'b, 'c, 'info>(
                 .token(pd.burn_ta)
                 .token_owner(pd.burn_ta)
                 .destination_token(pd.user_ta)
-                .destination_owner(pd.user_ta_owner)
+                .destination_owner(pd.recipient)
                 .token_record(pd.token_record)
                 .destination_token_record(pd.destination_token_record)
                 .edition(pd.edition)
@@ -394,8 +981,24 @@ pub fn perform_withdraw_v2<'b, 'c, 'info>(
                 .spl_token_program(pd.token_program)
                 .spl_ata_program(pd.associated_token_program)
                 .amount(withdraw_amount)
-                .invoke_signed(&[&[pd.mint.key().as_ref(), &[burn_bump]]])?;
+                .invoke_signed(&[&[pd.mint.key().as_ref(), &namespace_seed, &[burn_bump]]])?;
         } else {
+            let mint = pd.mint.key();
+            let burn_signer: &[&[&[u8]]] = &[&[mint.as_ref(), &namespace_seed, &[burn_bump]]];
+            if fee_amount > 0 {
+                anchor_spl::token::transfer(
+                    CpiContext::new_with_signer(
+                        pd.token_program.to_account_info(),
+                        anchor_spl::token::Transfer {
+                            from: pd.burn_ta.to_account_info(),
+                            to: pd.treasury_ta.to_account_info(),
+                            authority: pd.burn_ta.to_account_info(),
+                        },
+                        burn_signer,
+                    ),
+                    fee_amount,
+                )?;
+            }
             anchor_spl::token::transfer(
                 CpiContext::new_with_signer(
                     pd.token_program.to_account_info(),
@@ -404,9 +1007,9 @@ pub fn perform_withdraw_v2<'b, 'c, 'info>(
                         to: pd.user_ta.to_account_info(),
                         authority: pd.burn_ta.to_account_info(),
                     },
-                    &[&[pd.mint.key().as_ref(), &[burn_bump]]],
+                    burn_signer,
                 ),
-                withdraw_amount.into(),
+                withdraw_amount - fee_amount,
             )?;
         }
     }
@@ -429,3 +1032,238 @@ pub fn perform_withdraw_v2<'b, 'c, 'info>(
 
     Ok(())
 }
+
+pub fn perform_burn(pd: PerformBurn, burn_amount: u64, burn_bump: u8) -> Result<()> {
+    let locker = pd.locker.key();
+    let mint = pd.mint.key();
+    let mut balance =
+        load_or_init_balance(pd.balance, &locker, &mint, pd.admin, pd.system_program)?;
+    balance.check_unlocked(&Clock::get()?)?;
+    balance.debit(burn_amount)?;
+    store_balance(pd.balance, &balance)?;
+
+    let namespace_seed = pd.config.namespace_seed();
+    let burn_signer: &[&[&[u8]]] = &[&[mint.as_ref(), &namespace_seed, &[burn_bump]]];
+    if let Some(metadata) = pd.metadata {
+        // Token metadata closes the emptied token account itself.
+        BurnV1CpiBuilder::new(pd.token_metadata_program)
+            .authority(pd.burn_ta)
+            .metadata(metadata)
+            .edition(pd.edition)
+            .mint(pd.mint)
+            .token(pd.burn_ta)
+            .token_record(pd.token_record)
+            .system_program(pd.system_program)
+            .sysvar_instructions(pd.instructions)
+            .spl_token_program(pd.token_program)
+            .amount(burn_amount)
+            .invoke_signed(burn_signer)?;
+    } else {
+        anchor_spl::token::burn(
+            CpiContext::new_with_signer(
+                pd.token_program.to_account_info(),
+                anchor_spl::token::Burn {
+                    mint: pd.mint.to_account_info(),
+                    from: pd.burn_ta.to_account_info(),
+                    authority: pd.burn_ta.to_account_info(),
+                },
+                burn_signer,
+            ),
+            burn_amount,
+        )?;
+        if get_token_account(pd.burn_ta)?.amount == 0 {
+            anchor_spl::token::close_account(CpiContext::new_with_signer(
+                pd.token_program.to_account_info(),
+                anchor_spl::token::CloseAccount {
+                    account: pd.burn_ta.to_account_info(),
+                    destination: pd.admin.to_account_info(),
+                    authority: pd.burn_ta.to_account_info(),
+                },
+                burn_signer,
+            ))?;
+        }
+    }
+
+    emit!(BurnEvent {
+        locker,
+        mint,
+        amount: burn_amount,
+        co_signer: pd.co_signer,
+    });
+    Ok(())
+}
+
+pub fn perform_deposit_batch<'b, 'info>(
+    config: &'b mut Account<'info, Config>,
+    locker: &'b mut Account<'info, Locker>,
+    owner: &'b Signer<'info>,
+    locker_owner: &'b AccountInfo<'info>,
+    admin: &'b Signer<'info>,
+    system_program: &'b Program<'info, System>,
+    token_program: &'b Program<'info, Token>,
+    rent: &'b Sysvar<'info, Rent>,
+    treasury: &'b AccountInfo<'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+    deposit_amounts: Vec<u64>,
+    vault_bumps: Vec<u8>,
+    burn_bumps: Vec<u8>,
+    pnft_count: u8,
+    lock_until: Option<i64>,
+    mut delegate: Option<&mut Delegate>,
+) -> Result<()> {
+    const PNFT_CHUNK_SIZE: u8 = 9;
+    const NORMAL_CHUNK_SIZE: u8 = 5;
+    let pnft_ra_length = pnft_count * PNFT_CHUNK_SIZE + (if pnft_count > 0 { 3 } else { 0 });
+    if ((remaining_accounts.len() as u8) - pnft_ra_length) % NORMAL_CHUNK_SIZE != 0 {
+        return Err(error!(ErrorCode::WrongRemainingAccountsSize));
+    }
+    let token_metadata_program = &remaining_accounts[0];
+    let spl_ata_program_info = &remaining_accounts[1];
+    let instructions = &remaining_accounts[2];
+    let mut index = if pnft_count > 0 { 3 } else { 0 };
+    let mut mint_index: usize = 0;
+    while index < remaining_accounts.len() {
+        if let Some(delegate) = delegate.as_deref_mut() {
+            delegate.spend(remaining_accounts[index].key, deposit_amounts[mint_index])?;
+        }
+        let mut pd = PerformDepositV2 {
+            config,
+            locker,
+            mint: &remaining_accounts[index],
+            owner,
+            locker_owner,
+            admin,
+            user_ta: &remaining_accounts[index + 1],
+            vault_ta: &remaining_accounts[index + 2],
+            burn_ta: &remaining_accounts[index + 3],
+            balance: &remaining_accounts[index + 4],
+            metadata: None,
+            token_record: None,
+            destination_token_record: None,
+            edition: None,
+            token_metadata_program,
+            instructions,
+            spl_ata_program_info,
+            system_program,
+            token_program,
+            rent,
+        };
+        if (index as u8) < pnft_ra_length {
+            pd.metadata = Some(&remaining_accounts[index + 5]);
+            pd.token_record = Some(&remaining_accounts[index + 6]);
+            pd.destination_token_record = Some(&remaining_accounts[index + 7]);
+            pd.edition = Some(&remaining_accounts[index + 8]);
+        }
+        perform_deposit_v2(
+            pd,
+            vault_bumps[mint_index],
+            deposit_amounts[mint_index],
+            burn_bumps[mint_index],
+            lock_until,
+        )?;
+        index += if pnft_ra_length == 0 || (index as u8) > pnft_ra_length {
+            NORMAL_CHUNK_SIZE as usize
+        } else {
+            PNFT_CHUNK_SIZE as usize
+        };
+        mint_index += 1;
+    }
+    collect_fee(
+        owner,
+        treasury,
+        system_program,
+        config.deposit_fee.saturating_mul(mint_index as u64),
+    )
+}
+
+/// Pays out to the locker owner, or to `user_ta_owner` when `pay_signer` is
+/// set, e.g. the approving member of a guild locker.
+pub fn perform_withdraw_batch<'b, 'c, 'info>(
+    accounts: &'b mut WithdrawV2Batch<'info>,
+    remaining_accounts: &'c [AccountInfo<'info>],
+    withdraw_amounts: Vec<u64>,
+    vault_bumps: Vec<u8>,
+    burn_bumps: Vec<u8>,
+    pnft_count: u8,
+    pay_signer: bool,
+) -> Result<()> {
+    const PNFT_CHUNK_SIZE: u8 = 12;
+    const NORMAL_CHUNK_SIZE: u8 = 8;
+    let pnft_ra_length = pnft_count * PNFT_CHUNK_SIZE + (if pnft_count > 0 { 2 } else { 0 });
+    if ((remaining_accounts.len() as u8) - pnft_ra_length) % NORMAL_CHUNK_SIZE != 0 {
+        return Err(error!(ErrorCode::WrongRemainingAccountsSize));
+    }
+    let config: &'b mut Account<'info, Config> = &mut accounts.config;
+    let locker: &'b mut Account<'info, Locker> = &mut accounts.locker;
+    let user_ta_owner: &'b Signer<'info> = &accounts.user_ta_owner;
+    let recipient: &'b AccountInfo<'info> = if pay_signer {
+        user_ta_owner.as_ref()
+    } else {
+        &accounts.locker_owner
+    };
+    let system_program: &'b Program<'info, System> = &accounts.system_program;
+    let token_program: &'b Program<'info, Token> = &accounts.token_program;
+    let associated_token_program: &'b Program<'info, AssociatedToken> =
+        &accounts.associated_token_program;
+    let rent: &'b Sysvar<'info, Rent> = &accounts.rent;
+    let treasury: &'b AccountInfo<'info> = &accounts.treasury;
+    let mut delegate = accounts.delegate.as_deref_mut();
+    if let Some(delegate) = delegate.as_deref() {
+        delegate.check(Delegate::WITHDRAW)?;
+    }
+    let token_metadata_program = &remaining_accounts[0];
+    let instructions = &remaining_accounts[1];
+    let mut index = if pnft_count > 0 { 2 } else { 0 };
+    let mut mint_index = 0;
+    while index < remaining_accounts.len() {
+        if let Some(delegate) = delegate.as_deref_mut() {
+            delegate.spend(remaining_accounts[index].key, withdraw_amounts[mint_index])?;
+        }
+        let mut pd = PerformWithdrawV2 {
+            config,
+            locker,
+            mint: &remaining_accounts[index],
+            user_ta_owner,
+            recipient,
+            user_ta: &remaining_accounts[index + 1],
+            vault_ta: &remaining_accounts[index + 2],
+            vault_ta_owner: &remaining_accounts[index + 3],
+            burn_ta: &remaining_accounts[index + 4],
+            balance: Some(&remaining_accounts[index + 5]),
+            mint_policy: &remaining_accounts[index + 6],
+            delayed: false,
+            treasury,
+            treasury_ta: &remaining_accounts[index + 7],
+            metadata: None,
+            token_record: None,
+            destination_token_record: None,
+            edition: None,
+            token_metadata_program,
+            instructions,
+            system_program,
+            token_program,
+            associated_token_program,
+            rent,
+        };
+        if (index as u8) < pnft_ra_length {
+            pd.metadata = Some(&remaining_accounts[index + 8]);
+            pd.token_record = Some(&remaining_accounts[index + 9]);
+            pd.destination_token_record = Some(&remaining_accounts[index + 10]);
+            pd.edition = Some(&remaining_accounts[index + 11]);
+        }
+        perform_withdraw_v2(
+            pd,
+            withdraw_amounts[mint_index],
+            vault_bumps[mint_index],
+            burn_bumps[mint_index],
+        )?;
+
+        index += if pnft_ra_length == 0 || (index as u8) > pnft_ra_length {
+            NORMAL_CHUNK_SIZE as usize
+        } else {
+            PNFT_CHUNK_SIZE as usize
+        };
+        mint_index += 1;
+    }
+    Ok(())
+}
@@ -0,0 +1,33 @@
+//! Typed helpers for programs reading locker holdings through CPI.
+
+use crate::cpi::accounts::GetBalances;
+use crate::state::BalanceView;
+use anchor_lang::prelude::*;
+
+/// Returns the locker's ledger entries for `mints`. The `[b"balance", locker, mint]`
+/// accounts must be attached to `ctx` as remaining accounts, in order.
+pub fn get_balances<'info>(
+    ctx: CpiContext<'_, '_, '_, 'info, GetBalances<'info>>,
+    mints: Vec<Pubkey>,
+) -> Result<Vec<BalanceView>> {
+    Ok(crate::cpi::get_balances(ctx, mints)?.get())
+}
+
+/// Returns the locker's balance of a single mint.
+pub fn balance_of<'info>(
+    ctx: CpiContext<'_, '_, '_, 'info, GetBalances<'info>>,
+    mint: Pubkey,
+) -> Result<u64> {
+    Ok(get_balances(ctx, vec![mint])?
+        .first()
+        .map_or(0, |view| view.amount))
+}
+
+/// Returns whether the locker holds at least `amount` of `mint`.
+pub fn holds<'info>(
+    ctx: CpiContext<'_, '_, '_, 'info, GetBalances<'info>>,
+    mint: Pubkey,
+    amount: u64,
+) -> Result<bool> {
+    Ok(balance_of(ctx, mint)? >= amount)
+}
@@ -29,17 +29,23 @@ import * as anchor from "@coral-xyz/anchor";
 import { Context } from "mocha";
 import { AnchorProvider, Program, Provider, Wallet } from "@coral-xyz/anchor";
 import { Casier } from "../target/types/casier";
+import { CasierCaller } from "../target/types/casier_caller";
 import {
+  AccountMeta,
   AddressLookupTableAccount,
   ComputeBudgetProgram,
   Connection,
+  Ed25519Program,
   Keypair,
   PublicKey,
   SYSVAR_INSTRUCTIONS_PUBKEY,
   SYSVAR_RENT_PUBKEY,
+  SYSVAR_CLOCK_PUBKEY,
   Signer,
   SystemProgram,
+  Transaction,
   TransactionInstruction,
+  sendAndConfirmTransaction,
 } from "@solana/web3.js";
 import {
   toWeb3JsPublicKey,
@@ -50,14 +56,28 @@ import {
 } from "@metaplex-foundation/umi-web3js-adapters";
 import {
   ASSOCIATED_TOKEN_PROGRAM_ID,
+  AuthorityType,
+  createAccount,
   createAssociatedTokenAccount,
   createMint,
   mintTo,
   createMintToInstruction,
+  createTransferInstruction,
+  getAccount,
+  getAssociatedTokenAddressSync,
+  getMint,
+  getOrCreateAssociatedTokenAccount,
+  setAuthority,
   TOKEN_PROGRAM_ID,
 } from "@solana/spl-token";
-import { TxSender, createLookupTable, log } from "./utils";
-import { LockerSDK } from "../package/index";
+import {
+  TxSender,
+  assertFails,
+  createLookupTable,
+  log,
+  sleep,
+} from "./utils";
+import { LockerSDK, ValidUntil } from "../package/index";
 import {
   createCollection,
   mplCore,
@@ -69,6 +89,7 @@ import {
   fetchAssetV1,
 } from "@metaplex-foundation/mpl-core";
 import { assert } from "chai";
+import { keccak_256 } from "@noble/hashes/sha3";
 import { createNoopSigner } from "@metaplex-foundation/umi";
 import idl from "../target/idl/casier.json";
 anchor.setProvider(anchor.AnchorProvider.env());
@@ -105,6 +126,240 @@ interface CustomContext extends Context {
   splAurorianCollection: KeypairSigner;
 }
 
+/**
+ * Fungible mint without metadata, its authority is the locker program admin.
+ */
+async function createFundedMint(
+  ctx: CustomContext,
+  owners: PublicKey[],
+  amount: number
+): Promise<PublicKey> {
+  const admin = toWeb3JsKeypair(ctx.lockerProgramAdmin);
+  const mint = await createMint(
+    ctx.connection,
+    admin,
+    admin.publicKey,
+    null,
+    0
+  );
+  for (const owner of owners) {
+    const ta = await getOrCreateAssociatedTokenAccount(
+      ctx.connection,
+      admin,
+      mint,
+      owner,
+      true
+    );
+    await mintTo(ctx.connection, admin, mint, ta.address, admin, amount);
+  }
+  return mint;
+}
+
+async function createFundedUser(ctx: CustomContext): Promise<Keypair> {
+  const user = Keypair.generate();
+  await ctx.umi.rpc.airdrop(fromWeb3JsPublicKey(user.publicKey), sol(10));
+  return user;
+}
+
+/**
+ * Sends with preflight so that rejections carry the program logs, the first
+ * signer pays.
+ */
+async function sendIxs(
+  ctx: CustomContext,
+  ixs: TransactionInstruction[],
+  signers: Signer[]
+): Promise<string> {
+  const tx = new Transaction().add(
+    ComputeBudgetProgram.setComputeUnitLimit({ units: 2_000_000 }),
+    ...ixs
+  );
+  return sendAndConfirmTransaction(ctx.connection, tx, signers);
+}
+
+async function deposit(
+  ctx: CustomContext,
+  user: Keypair,
+  mint: PublicKey,
+  amount: number,
+  validUntil: ValidUntil | null = null,
+  lockUntil: anchor.BN | null = null,
+  locker: PublicKey | null = null
+): Promise<string> {
+  const ixs = await ctx.lsdk.depositInstruction(
+    [mint],
+    user.publicKey,
+    [new anchor.BN(amount)],
+    validUntil,
+    lockUntil,
+    locker
+  );
+  return sendIxs(ctx, ixs, [user, toWeb3JsKeypair(ctx.lockerProgramAdmin)]);
+}
+
+async function withdraw(
+  ctx: CustomContext,
+  user: Keypair,
+  mint: PublicKey,
+  amount: number,
+  validUntil: ValidUntil | null = null,
+  locker: PublicKey | null = null
+): Promise<string> {
+  const ixs = await ctx.lsdk.withdrawInstruction(
+    [mint],
+    user.publicKey,
+    [user.publicKey],
+    [new anchor.BN(amount)],
+    undefined,
+    validUntil,
+    locker
+  );
+  return sendIxs(ctx, ixs, [user, toWeb3JsKeypair(ctx.lockerProgramAdmin)]);
+}
+
+/**
+ * Remaining accounts of `withdrawVested` and `finalizeWithdraw`, paying
+ * `mint` out of the pool to the ATA of `owner`.
+ */
+async function poolWithdrawAccounts(
+  ctx: CustomContext,
+  owner: PublicKey,
+  mint: PublicKey
+): Promise<{
+  remainingAccounts: AccountMeta[];
+  vaultBump: number;
+  burnBump: number;
+}> {
+  const [vaultTa, vaultBump] = PublicKey.findProgramAddressSync(
+    [mint.toBuffer(), owner.toBuffer()],
+    ctx.program.programId
+  );
+  const [burnTa, burnBump] = await ctx.lsdk.withdrawSourcePDA(mint);
+  const remainingAccounts = [
+    toWeb3JsPublicKey(MPL_TOKEN_METADATA_PROGRAM_ID),
+    SYSVAR_INSTRUCTIONS_PUBKEY,
+    mint,
+    getAssociatedTokenAddressSync(mint, owner),
+    vaultTa,
+    owner,
+    burnTa,
+    ctx.lsdk.mintPolicyPDA(mint),
+    ctx.lsdk.treasuryTaPDA(mint),
+  ].map((pubkey, index) => ({
+    pubkey,
+    isWritable: index > 1,
+    isSigner: false,
+  }));
+  return { remainingAccounts, vaultBump, burnBump };
+}
+
+async function initLocker(ctx: CustomContext, owner: Keypair): Promise<string> {
+  const ix = await ctx.program.methods
+    .initLockerV2()
+    .accounts({
+      locker: ctx.lsdk.getLockerPDA(owner.publicKey),
+      owner: owner.publicKey,
+      systemProgram: SystemProgram.programId,
+      rent: SYSVAR_RENT_PUBKEY,
+    })
+    .instruction();
+  return sendIxs(ctx, [ix], [owner]);
+}
+
+async function initMintPolicy(
+  ctx: CustomContext,
+  mint: PublicKey
+): Promise<string> {
+  const admin = toWeb3JsKeypair(ctx.lockerProgramAdmin);
+  const ix = await ctx.program.methods
+    .initMintPolicy()
+    .accounts({
+      config: ctx.lsdk.configPDA,
+      mint,
+      mintPolicy: ctx.lsdk.mintPolicyPDA(mint),
+      admin: admin.publicKey,
+      systemProgram: SystemProgram.programId,
+    })
+    .instruction();
+  return sendIxs(ctx, [ix], [admin]);
+}
+
+/**
+ * Unix timestamp the program reads from the clock sysvar.
+ */
+async function chainTime(ctx: CustomContext): Promise<number> {
+  const clock = await ctx.connection.getAccountInfo(SYSVAR_CLOCK_PUBKEY);
+  return new anchor.BN(clock.data.subarray(32, 40), "le").toNumber();
+}
+
+async function lockerBalance(
+  ctx: CustomContext,
+  locker: PublicKey,
+  mint: PublicKey
+): Promise<number> {
+  const balance = await ctx.program.account.balance.fetchNullable(
+    ctx.lsdk.balancePDA(locker, mint)
+  );
+  return balance ? balance.amount.toNumber() : 0;
+}
+
+async function tokenBalance(
+  ctx: CustomContext,
+  owner: PublicKey,
+  mint: PublicKey
+): Promise<number> {
+  const ta = await getAccount(
+    ctx.connection,
+    getAssociatedTokenAddressSync(mint, owner, true)
+  ).catch(() => null);
+  return ta ? Number(ta.amount) : 0;
+}
+
+/**
+ * Marks the optional signer accounts of `ix`, e.g. owners that may sign in
+ * place of the admin.
+ */
+function signedBy(
+  ix: TransactionInstruction,
+  signers: PublicKey[]
+): TransactionInstruction {
+  for (const key of ix.keys) {
+    if (signers.some((signer) => signer.equals(key.pubkey))) {
+      key.isSigner = true;
+    }
+  }
+  return ix;
+}
+
+/**
+ * Leaf of a distribution entitlement, hashed like `Distribution::leaf`.
+ */
+function merkleLeaf(
+  index: number,
+  recipient: PublicKey,
+  mint: PublicKey,
+  amount: number
+): Buffer {
+  return Buffer.from(
+    keccak_256(
+      Buffer.concat([
+        Buffer.from([0]),
+        new anchor.BN(index).toArrayLike(Buffer, "le", 4),
+        recipient.toBuffer(),
+        mint.toBuffer(),
+        new anchor.BN(amount).toArrayLike(Buffer, "le", 8),
+      ])
+    )
+  );
+}
+
+function merkleNode(a: Buffer, b: Buffer): Buffer {
+  const [left, right] = Buffer.compare(a, b) <= 0 ? [a, b] : [b, a];
+  return Buffer.from(
+    keccak_256(Buffer.concat([Buffer.from([1]), left, right]))
+  );
+}
+
 describe("Mix", function () {
   before(async function (this: CustomContext) {
     const connection = new Connection("http://127.0.0.1:8899", "recent");
@@ -501,6 +756,43 @@ describe("Mix", function () {
     }
   });
 
+  it("Refuse deposits into a token account that is not the pool", async function (this: CustomContext) {
+    const user = this.users[0];
+    const mint = mints[0];
+    const [burnTa] = PublicKey.findProgramAddressSync(
+      [mint.toBuffer()],
+      this.program.programId
+    );
+    // Owned by another key, the program could never pay these tokens out.
+    const fakePool = await createAssociatedTokenAccount(
+      this.connection,
+      user,
+      mint,
+      Keypair.generate().publicKey
+    );
+    const ixs = await this.lsdk.depositInstruction([mint], user.publicKey, [
+      new anchor.BN(1),
+    ]);
+    for (const ix of ixs) {
+      for (const key of ix.keys) {
+        if (key.pubkey.equals(burnTa)) {
+          key.pubkey = fakePool;
+        }
+      }
+    }
+    let logs: string[] = [];
+    try {
+      await anchor.web3.sendAndConfirmTransaction(
+        this.connection,
+        new anchor.web3.Transaction().add(...ixs),
+        [user, toWeb3JsKeypair(this.lockerProgramAdmin)]
+      );
+    } catch (e) {
+      logs = e.logs ?? [];
+    }
+    assert.include(logs.join("\n"), "InvalidVault");
+  });
+
   it("Deposit spl aurorian & receive core one", async function (this: CustomContext) {
     const userIndex = 0;
     const user = this.users[userIndex];
@@ -621,4 +913,2385 @@ describe("Mix", function () {
     //   assert.strictEqual(burnAmount, (index + 2).toString());
     // }
   });
+
+  it("Reject operations past their deadline", async function (this: CustomContext) {
+    const user = this.users[0];
+    const locker = this.lsdk.getLockerPDA(user.publicKey);
+    const mint = await createFundedMint(this, [user.publicKey], 10);
+    const now = await chainTime(this);
+
+    await assertFails(
+      deposit(this, user, mint, 4, {
+        timestamp: { 0: new anchor.BN(now - 60) },
+      }),
+      "OperationExpired"
+    );
+    await deposit(this, user, mint, 4, {
+      timestamp: { 0: new anchor.BN(now + 60) },
+    });
+    assert.strictEqual(await lockerBalance(this, locker, mint), 4);
+
+    await assertFails(
+      withdraw(this, user, mint, 4, { slot: { 0: new anchor.BN(0) } }),
+      "OperationExpired"
+    );
+    const slot = await this.connection.getSlot();
+    await withdraw(this, user, mint, 4, {
+      slot: { 0: new anchor.BN(slot + 150) },
+    });
+    assert.strictEqual(await lockerBalance(this, locker, mint), 0);
+    assert.strictEqual(await tokenBalance(this, user.publicKey, mint), 10);
+  });
+
+  it("Redeem admin signed vouchers", async function (this: CustomContext) {
+    const user = this.users[0];
+    const locker = this.lsdk.getLockerPDA(user.publicKey);
+    const mint = await createFundedMint(this, [user.publicKey], 10);
+    await deposit(this, user, mint, 5);
+    const amount = new anchor.BN(2);
+    const voucher = async (expiry: anchor.BN) => {
+      const { space: nonce } = await this.program.account.locker.fetch(locker);
+      const message = this.lsdk.voucherMessage(
+        locker,
+        mint,
+        amount,
+        nonce,
+        expiry
+      );
+      const signature = await this.lockerProgramAdmin.signMessage(message);
+      const ixs = await this.lsdk.redeemVoucherInstruction(
+        mint,
+        user.publicKey,
+        amount,
+        nonce,
+        expiry,
+        signature
+      );
+      return { ixs, message };
+    };
+    const now = await chainTime(this);
+
+    const expired = await voucher(new anchor.BN(now - 60));
+    await assertFails(sendIxs(this, expired.ixs, [user]), "OperationExpired");
+
+    const forged = await voucher(new anchor.BN(now + 600));
+    forged.ixs[0] = Ed25519Program.createInstructionWithPrivateKey({
+      privateKey: Keypair.generate().secretKey,
+      message: forged.message,
+    });
+    await assertFails(
+      sendIxs(this, forged.ixs, [user]),
+      "InvalidVoucherSignature"
+    );
+
+    const { ixs } = await voucher(new anchor.BN(now + 600));
+    await sendIxs(this, ixs, [user]);
+    assert.strictEqual(await lockerBalance(this, locker, mint), 3);
+    assert.strictEqual(await tokenBalance(this, user.publicKey, mint), 7);
+
+    // The locker nonce moved on, the same voucher can't be redeemed twice.
+    await assertFails(sendIxs(this, ixs, [user]), "InvalidBeforeState");
+  });
+
+  it("Claim merkle distributions", async function (this: CustomContext) {
+    const admin = toWeb3JsKeypair(this.lockerProgramAdmin);
+    const [user0, user1] = this.users;
+    const mint = await createFundedMint(this, [admin.publicKey], 10);
+    const [burnTa, burnBump] = PublicKey.findProgramAddressSync(
+      [mint.toBuffer()],
+      this.program.programId
+    );
+    const leaves = [
+      merkleLeaf(0, user0.publicKey, mint, 3),
+      merkleLeaf(1, user1.publicKey, mint, 4),
+    ];
+    const root = merkleNode(leaves[0], leaves[1]);
+    const initDistribution = async (id: anchor.BN, total: number) => {
+      const [distribution] = PublicKey.findProgramAddressSync(
+        [
+          anchor.utils.bytes.utf8.encode("distribution"),
+          id.toArrayLike(Buffer, "le", 8),
+        ],
+        this.program.programId
+      );
+      const ix = await this.program.methods
+        .initDistribution(
+          id,
+          Array.from(root),
+          2,
+          new anchor.BN(total),
+          burnBump
+        )
+        .accounts({
+          config: this.lsdk.configPDA,
+          distribution,
+          mint,
+          treasuryTa: getAssociatedTokenAddressSync(mint, admin.publicKey),
+          burnTa,
+          admin: admin.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          rent: SYSVAR_RENT_PUBKEY,
+        })
+        .instruction();
+      await sendIxs(this, [ix], [admin]);
+      return distribution;
+    };
+    const claim = (
+      distribution: PublicKey,
+      recipient: PublicKey,
+      index: number,
+      amount: number,
+      proof: Buffer[]
+    ) =>
+      this.program.methods
+        .claim(
+          index,
+          new anchor.BN(amount),
+          proof.map((node) => Array.from(node)),
+          burnBump
+        )
+        .accounts({
+          config: this.lsdk.configPDA,
+          distribution,
+          mint,
+          recipient,
+          recipientTa: getAssociatedTokenAddressSync(mint, recipient),
+          burnTa,
+          payer: admin.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          instructions: SYSVAR_INSTRUCTIONS_PUBKEY,
+        })
+        .instruction();
+
+    const distribution = await initDistribution(new anchor.BN(Date.now()), 7);
+    await assertFails(
+      sendIxs(
+        this,
+        [await claim(distribution, user1.publicKey, 1, 5, [leaves[0]])],
+        [admin]
+      ),
+      "InvalidMerkleProof"
+    );
+    await sendIxs(
+      this,
+      [await claim(distribution, user0.publicKey, 0, 3, [leaves[1]])],
+      [admin]
+    );
+    assert.strictEqual(await tokenBalance(this, user0.publicKey, mint), 3);
+    await assertFails(
+      sendIxs(
+        this,
+        [await claim(distribution, user0.publicKey, 0, 3, [leaves[1]])],
+        [admin]
+      ),
+      "AlreadyClaimed"
+    );
+    await sendIxs(
+      this,
+      [await claim(distribution, user1.publicKey, 1, 4, [leaves[0]])],
+      [admin]
+    );
+    assert.strictEqual(await tokenBalance(this, user1.publicKey, mint), 4);
+
+    // Claims can't take more than the distribution was funded with.
+    const underfunded = await initDistribution(
+      new anchor.BN(Date.now() + 1),
+      3
+    );
+    await assertFails(
+      sendIxs(
+        this,
+        [await claim(underfunded, user1.publicKey, 1, 4, [leaves[0]])],
+        [admin]
+      ),
+      "DistributionExhausted"
+    );
+  });
+
+  it("Transfer balances between lockers", async function (this: CustomContext) {
+    const admin = toWeb3JsKeypair(this.lockerProgramAdmin);
+    const [user0, user1] = this.users;
+    const mint = await createFundedMint(this, [user0.publicKey], 10);
+    await deposit(this, user0, mint, 6);
+    const locker0 = this.lsdk.getLockerPDA(user0.publicKey);
+    const locker1 = this.lsdk.getLockerPDA(user1.publicKey);
+    const transfer = async (amount: number, payer: PublicKey) =>
+      this.lsdk.transferBetweenLockersInstruction(
+        [mint],
+        user0.publicKey,
+        user1.publicKey,
+        [new anchor.BN(amount)],
+        payer
+      );
+
+    await sendIxs(
+      this,
+      [
+        signedBy(await transfer(2, user0.publicKey), [
+          user0.publicKey,
+          user1.publicKey,
+        ]),
+      ],
+      [user0, user1]
+    );
+    assert.strictEqual(await lockerBalance(this, locker0, mint), 4);
+    assert.strictEqual(await lockerBalance(this, locker1, mint), 2);
+    // Tokens stay in the pool.
+    assert.strictEqual(await tokenBalance(this, user1.publicKey, mint), 0);
+
+    await sendIxs(
+      this,
+      [signedBy(await transfer(1, admin.publicKey), [admin.publicKey])],
+      [admin]
+    );
+    assert.strictEqual(await lockerBalance(this, locker0, mint), 3);
+    assert.strictEqual(await lockerBalance(this, locker1, mint), 3);
+
+    await assertFails(
+      sendIxs(
+        this,
+        [signedBy(await transfer(1, user0.publicKey), [user0.publicKey])],
+        [user0]
+      ),
+      "Unauthorized"
+    );
+    await assertFails(
+      sendIxs(
+        this,
+        [
+          signedBy(await transfer(4, user0.publicKey), [
+            user0.publicKey,
+            user1.publicKey,
+          ]),
+        ],
+        [user0, user1]
+      ),
+      "InsufficientLockerBalance"
+    );
+    assert.strictEqual(await lockerBalance(this, locker0, mint), 3);
+  });
+
+  it("Trade balances through offers", async function (this: CustomContext) {
+    const [maker, taker] = this.users;
+    const outsider = await createFundedUser(this);
+    const mintA = await createFundedMint(this, [maker.publicKey], 5);
+    const mintB = await createFundedMint(
+      this,
+      [taker.publicKey, outsider.publicKey],
+      5
+    );
+    await deposit(this, maker, mintA, 3);
+    await deposit(this, taker, mintB, 3);
+    await deposit(this, outsider, mintB, 3);
+    const makerLocker = this.lsdk.getLockerPDA(maker.publicKey);
+    const takerLocker = this.lsdk.getLockerPDA(taker.publicKey);
+    const now = await chainTime(this);
+    const createOffer = async (id: number, want: number, expiry: number) =>
+      this.lsdk.createOfferInstruction(
+        maker.publicKey,
+        new anchor.BN(id),
+        [{ mint: mintA, amount: new anchor.BN(2) }],
+        [{ mint: mintB, amount: new anchor.BN(want) }],
+        new anchor.BN(expiry),
+        taker.publicKey
+      );
+    const acceptOffer = async (id: number, user: Keypair) =>
+      sendIxs(
+        this,
+        [
+          await this.lsdk.acceptOfferInstruction(
+            maker.publicKey,
+            new anchor.BN(id),
+            user.publicKey
+          ),
+        ],
+        [user]
+      );
+
+    await assertFails(
+      sendIxs(this, [await createOffer(0, 2, now - 10)], [maker]),
+      "OperationExpired"
+    );
+
+    await sendIxs(this, [await createOffer(1, 5, now + 600)], [maker]);
+    await assertFails(acceptOffer(1, taker), "InsufficientLockerBalance");
+    await sendIxs(
+      this,
+      [
+        await this.lsdk.cancelOfferInstruction(
+          maker.publicKey,
+          new anchor.BN(1)
+        ),
+      ],
+      [maker]
+    );
+
+    await sendIxs(this, [await createOffer(2, 2, now + 600)], [maker]);
+    await assertFails(acceptOffer(2, outsider), "Unauthorized");
+    await acceptOffer(2, taker);
+    assert.strictEqual(await lockerBalance(this, makerLocker, mintA), 1);
+    assert.strictEqual(await lockerBalance(this, makerLocker, mintB), 2);
+    assert.strictEqual(await lockerBalance(this, takerLocker, mintA), 2);
+    assert.strictEqual(await lockerBalance(this, takerLocker, mintB), 1);
+    // The offer is closed once accepted.
+    assert.isNull(
+      await this.program.account.offer.fetchNullable(
+        this.lsdk.offerPDA(makerLocker, new anchor.BN(2))
+      )
+    );
+  });
+
+  it("Lock deposits until a timestamp", async function (this: CustomContext) {
+    const admin = toWeb3JsKeypair(this.lockerProgramAdmin);
+    const user = this.users[0];
+    const mint = await createFundedMint(this, [user.publicKey], 10);
+    const locker = this.lsdk.getLockerPDA(user.publicKey);
+    const lockFor = async (seconds: number) =>
+      new anchor.BN((await chainTime(this)) + seconds);
+
+    await deposit(this, user, mint, 4, null, await lockFor(3));
+    await assertFails(withdraw(this, user, mint, 1), "BalanceLocked");
+    await sleep(5000);
+    await withdraw(this, user, mint, 1);
+    assert.strictEqual(await lockerBalance(this, locker, mint), 3);
+
+    // Only the admin can lift a lock before it expires.
+    await deposit(this, user, mint, 2, null, await lockFor(3600));
+    await assertFails(withdraw(this, user, mint, 1), "BalanceLocked");
+    const overrideLock = (signer: Keypair) =>
+      this.program.methods
+        .overrideLock()
+        .accounts({
+          config: this.lsdk.configPDA,
+          locker,
+          balance: this.lsdk.balancePDA(locker, mint),
+          admin: signer.publicKey,
+        })
+        .instruction();
+    await assertFails(
+      sendIxs(this, [await overrideLock(user)], [user]),
+      "ConstraintHasOne"
+    );
+    await sendIxs(this, [await overrideLock(admin)], [admin]);
+    await withdraw(this, user, mint, 1);
+    assert.strictEqual(await lockerBalance(this, locker, mint), 4);
+  });
+
+  it("Withdraw vested rewards", async function (this: CustomContext) {
+    const admin = toWeb3JsKeypair(this.lockerProgramAdmin);
+    const user = this.users[0];
+    const locker = this.lsdk.getLockerPDA(user.publicKey);
+    const now = await chainTime(this);
+    const createSchedule = async (
+      mint: PublicKey,
+      start: number,
+      cliff: number,
+      end: number
+    ) => {
+      const [burnTa, burnBump] = await this.lsdk.withdrawSourcePDA(mint);
+      const [vestingSchedule] = PublicKey.findProgramAddressSync(
+        [
+          anchor.utils.bytes.utf8.encode("vesting"),
+          locker.toBuffer(),
+          mint.toBuffer(),
+        ],
+        this.program.programId
+      );
+      const ix = await this.program.methods
+        .createVestingSchedule(
+          new anchor.BN(5),
+          new anchor.BN(start),
+          new anchor.BN(cliff),
+          new anchor.BN(end),
+          burnBump
+        )
+        .accounts({
+          config: this.lsdk.configPDA,
+          locker,
+          mint,
+          vestingSchedule,
+          treasuryTa: getAssociatedTokenAddressSync(mint, admin.publicKey),
+          burnTa,
+          admin: admin.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          rent: SYSVAR_RENT_PUBKEY,
+        })
+        .instruction();
+      await sendIxs(this, [ix], [admin]);
+      return vestingSchedule;
+    };
+    const withdrawVested = async (
+      vestingSchedule: PublicKey,
+      mint: PublicKey,
+      amount: number
+    ) => {
+      const { remainingAccounts, vaultBump, burnBump } =
+        await poolWithdrawAccounts(this, user.publicKey, mint);
+      const ix = await this.program.methods
+        .withdrawVested(new anchor.BN(amount), vaultBump, burnBump)
+        .accounts({
+          config: this.lsdk.configPDA,
+          locker,
+          vestingSchedule,
+          balance: this.lsdk.balancePDA(locker, mint),
+          owner: user.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          rent: SYSVAR_RENT_PUBKEY,
+          treasury: this.lsdk.treasuryPDA(),
+        })
+        .remainingAccounts(remainingAccounts)
+        .instruction();
+      return sendIxs(this, [ix], [user]);
+    };
+
+    const vested = await createFundedMint(this, [admin.publicKey], 5);
+    await assertFails(
+      createSchedule(vested, now - 100, now + 10, now - 10),
+      "InvalidVestingSchedule"
+    );
+    const vestedSchedule = await createSchedule(
+      vested,
+      now - 100,
+      now - 100,
+      now - 10
+    );
+    await assertFails(
+      withdrawVested(vestedSchedule, vested, 6),
+      "VestingAmountExceeded"
+    );
+
+    // Vested rewards count against the locker daily cap.
+    const setLockerDailyCap = async (cap: number) =>
+      sendIxs(
+        this,
+        [
+          await this.program.methods
+            .setLockerDailyCap(new anchor.BN(cap))
+            .accounts({ config: this.lsdk.configPDA, admin: admin.publicKey })
+            .instruction(),
+        ],
+        [admin]
+      );
+    await setLockerDailyCap(3);
+    try {
+      await withdrawVested(vestedSchedule, vested, 3);
+      await assertFails(
+        withdrawVested(vestedSchedule, vested, 1),
+        "LockerDailyCapExceeded"
+      );
+    } finally {
+      await setLockerDailyCap(0);
+    }
+    await withdrawVested(vestedSchedule, vested, 2);
+    assert.strictEqual(await tokenBalance(this, user.publicKey, vested), 5);
+    await assertFails(
+      withdrawVested(vestedSchedule, vested, 1),
+      "VestingAmountExceeded"
+    );
+
+    // Nothing unlocks before the cliff.
+    const unvested = await createFundedMint(this, [admin.publicKey], 5);
+    const unvestedSchedule = await createSchedule(
+      unvested,
+      now,
+      now + 3600,
+      now + 7200
+    );
+    await assertFails(
+      withdrawVested(unvestedSchedule, unvested, 1),
+      "VestingAmountExceeded"
+    );
+  });
+
+  it("Rate limit withdrawals of a mint", async function (this: CustomContext) {
+    const admin = toWeb3JsKeypair(this.lockerProgramAdmin);
+    const user = this.users[0];
+    const mint = await createFundedMint(this, [user.publicKey], 10);
+    await deposit(this, user, mint, 5);
+    await initMintPolicy(this, mint);
+    const setRateLimit = (signer: Keypair) =>
+      this.program.methods
+        .setWithdrawRateLimit(new anchor.BN(1_000_000), new anchor.BN(2))
+        .accounts({
+          config: this.lsdk.configPDA,
+          mintPolicy: this.lsdk.mintPolicyPDA(mint),
+          admin: signer.publicKey,
+        })
+        .instruction();
+    await assertFails(
+      sendIxs(this, [await setRateLimit(user)], [user]),
+      "ConstraintHasOne"
+    );
+    await sendIxs(this, [await setRateLimit(admin)], [admin]);
+
+    await withdraw(this, user, mint, 2);
+    await assertFails(
+      withdraw(this, user, mint, 1),
+      "WithdrawRateLimitExceeded"
+    );
+    assert.strictEqual(
+      await lockerBalance(this, this.lsdk.getLockerPDA(user.publicKey), mint),
+      3
+    );
+  });
+
+  it("Cap daily withdrawals per locker", async function (this: CustomContext) {
+    const admin = toWeb3JsKeypair(this.lockerProgramAdmin);
+    const user = this.users[0];
+    const locker = this.lsdk.getLockerPDA(user.publicKey);
+    const mint = await createFundedMint(this, [user.publicKey], 10);
+    await deposit(this, user, mint, 10);
+    const setLockerDailyCap = async (cap: number) =>
+      sendIxs(
+        this,
+        [
+          await this.program.methods
+            .setLockerDailyCap(new anchor.BN(cap))
+            .accounts({ config: this.lsdk.configPDA, admin: admin.publicKey })
+            .instruction(),
+        ],
+        [admin]
+      );
+    const raiseLockerDailyCap = (signer: Keypair, cap: number) =>
+      this.program.methods
+        .raiseLockerDailyCap(new anchor.BN(cap))
+        .accounts({
+          config: this.lsdk.configPDA,
+          locker,
+          admin: signer.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .instruction();
+
+    await setLockerDailyCap(2);
+    try {
+      await withdraw(this, user, mint, 2);
+      await assertFails(
+        withdraw(this, user, mint, 1),
+        "LockerDailyCapExceeded"
+      );
+
+      await assertFails(
+        sendIxs(this, [await raiseLockerDailyCap(user, 5)], [user]),
+        "ConstraintHasOne"
+      );
+      await sendIxs(this, [await raiseLockerDailyCap(admin, 5)], [admin]);
+      await withdraw(this, user, mint, 3);
+      await assertFails(
+        withdraw(this, user, mint, 1),
+        "LockerDailyCapExceeded"
+      );
+
+      // The raise covers every mint of the locker.
+      const other = await createFundedMint(this, [user.publicKey], 10);
+      await deposit(this, user, other, 10);
+      await withdraw(this, user, other, 5);
+      await assertFails(
+        withdraw(this, user, other, 1),
+        "LockerDailyCapExceeded"
+      );
+      await sendIxs(this, [await raiseLockerDailyCap(admin, 0)], [admin]);
+    } finally {
+      await setLockerDailyCap(0);
+    }
+    assert.strictEqual(await lockerBalance(this, locker, mint), 5);
+
+    // A mint policy cap applies on its own.
+    const capped = await createFundedMint(this, [user.publicKey], 10);
+    await deposit(this, user, capped, 5);
+    await initMintPolicy(this, capped);
+    await sendIxs(
+      this,
+      [
+        await this.program.methods
+          .setMintLockerDailyCap(new anchor.BN(1))
+          .accounts({
+            config: this.lsdk.configPDA,
+            mintPolicy: this.lsdk.mintPolicyPDA(capped),
+            admin: admin.publicKey,
+          })
+          .instruction(),
+      ],
+      [admin]
+    );
+    await withdraw(this, user, capped, 1);
+    await assertFails(
+      withdraw(this, user, capped, 1),
+      "LockerDailyCapExceeded"
+    );
+  });
+
+  it("Delay withdrawals of a mint", async function (this: CustomContext) {
+    const admin = toWeb3JsKeypair(this.lockerProgramAdmin);
+    const user = this.users[0];
+    const outsider = await createFundedUser(this);
+    const locker = this.lsdk.getLockerPDA(user.publicKey);
+    const mint = await createFundedMint(this, [user.publicKey], 10);
+    await deposit(this, user, mint, 5);
+    await initMintPolicy(this, mint);
+    await sendIxs(
+      this,
+      [
+        await this.program.methods
+          .setWithdrawDelay(new anchor.BN(3))
+          .accounts({
+            config: this.lsdk.configPDA,
+            mintPolicy: this.lsdk.mintPolicyPDA(mint),
+            admin: admin.publicKey,
+          })
+          .instruction(),
+      ],
+      [admin]
+    );
+    const [pendingWithdrawal] = PublicKey.findProgramAddressSync(
+      [
+        anchor.utils.bytes.utf8.encode("pending_withdraw"),
+        locker.toBuffer(),
+        mint.toBuffer(),
+      ],
+      this.program.programId
+    );
+    const requestWithdraw = async (amount: number) => {
+      const { space } = await this.program.account.locker.fetch(locker);
+      const ix = await this.program.methods
+        .requestWithdraw(new anchor.BN(amount), space)
+        .accounts({
+          config: this.lsdk.configPDA,
+          locker,
+          mint,
+          pendingWithdrawal,
+          balance: this.lsdk.balancePDA(locker, mint),
+          mintPolicy: this.lsdk.mintPolicyPDA(mint),
+          owner: user.publicKey,
+          admin: admin.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .instruction();
+      return sendIxs(this, [ix], [user, admin]);
+    };
+    const finalizeWithdraw = async () => {
+      const { remainingAccounts, vaultBump, burnBump } =
+        await poolWithdrawAccounts(this, user.publicKey, mint);
+      const ix = await this.program.methods
+        .finalizeWithdraw(vaultBump, burnBump)
+        .accounts({
+          config: this.lsdk.configPDA,
+          locker,
+          pendingWithdrawal,
+          owner: user.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          rent: SYSVAR_RENT_PUBKEY,
+          treasury: this.lsdk.treasuryPDA(),
+        })
+        .remainingAccounts(remainingAccounts)
+        .instruction();
+      return sendIxs(this, [ix], [user]);
+    };
+    const cancelWithdraw = async (authority: Keypair) => {
+      const ix = await this.program.methods
+        .cancelWithdraw()
+        .accounts({
+          config: this.lsdk.configPDA,
+          locker,
+          pendingWithdrawal,
+          balance: this.lsdk.balancePDA(locker, mint),
+          owner: user.publicKey,
+          authority: authority.publicKey,
+        })
+        .instruction();
+      return sendIxs(this, [ix], [authority]);
+    };
+
+    await assertFails(
+      withdraw(this, user, mint, 1),
+      "DelayedWithdrawalRequired"
+    );
+    await requestWithdraw(2);
+    assert.strictEqual(await lockerBalance(this, locker, mint), 3);
+    await assertFails(finalizeWithdraw(), "WithdrawalDelayNotElapsed");
+    await sleep(5000);
+    await finalizeWithdraw();
+    assert.strictEqual(await tokenBalance(this, user.publicKey, mint), 7);
+
+    // The owner or the admin can cancel while the request is pending.
+    await requestWithdraw(1);
+    await assertFails(cancelWithdraw(outsider), "Unauthorized");
+    await cancelWithdraw(user);
+    assert.strictEqual(await lockerBalance(this, locker, mint), 3);
+    assert.isNull(
+      await this.program.account.pendingWithdrawal.fetchNullable(
+        pendingWithdrawal
+      )
+    );
+  });
+
+  it("Claw back items to the quarantine", async function (this: CustomContext) {
+    const admin = toWeb3JsKeypair(this.lockerProgramAdmin);
+    const user = this.users[0];
+    const locker = this.lsdk.getLockerPDA(user.publicKey);
+    const quarantine = Keypair.generate().publicKey;
+    const mint = await createFundedMint(this, [user.publicKey], 10);
+    await deposit(this, user, mint, 5);
+    const quarantineTa = await getOrCreateAssociatedTokenAccount(
+      this.connection,
+      admin,
+      mint,
+      quarantine
+    );
+    const [burnTa, burnBump] = await this.lsdk.withdrawSourcePDA(mint);
+    const setQuarantine = async (key: PublicKey) =>
+      sendIxs(
+        this,
+        [
+          await this.program.methods
+            .setQuarantine(key)
+            .accounts({ config: this.lsdk.configPDA, admin: admin.publicKey })
+            .instruction(),
+        ],
+        [admin]
+      );
+    const clawback = async (
+      signer: Keypair,
+      amount: number,
+      configQuarantine = quarantine
+    ) => {
+      const ix = await this.program.methods
+        .clawback(new anchor.BN(amount), burnBump, 1)
+        .accounts({
+          config: this.lsdk.configPDA,
+          locker,
+          balance: this.lsdk.balancePDA(locker, mint),
+          mint,
+          burnTa,
+          quarantine: configQuarantine,
+          quarantineTa: quarantineTa.address,
+          mintPolicy: this.lsdk.mintPolicyPDA(mint),
+          admin: signer.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        })
+        .instruction();
+      return sendIxs(this, [ix], [signer]);
+    };
+
+    await assertFails(
+      clawback(admin, 1, PublicKey.default),
+      "QuarantineNotSet"
+    );
+    await setQuarantine(quarantine);
+    await assertFails(
+      setQuarantine(Keypair.generate().publicKey),
+      "QuarantineAlreadySet"
+    );
+    await assertFails(clawback(user, 1), "ConstraintHasOne");
+    await clawback(admin, 2);
+    assert.strictEqual(await lockerBalance(this, locker, mint), 3);
+    assert.strictEqual(await tokenBalance(this, quarantine, mint), 2);
+    await assertFails(clawback(admin, 4), "InsufficientLockerBalance");
+
+    // Clawbacks go through the mint rate limit.
+    await initMintPolicy(this, mint);
+    await sendIxs(
+      this,
+      [
+        await this.program.methods
+          .setWithdrawRateLimit(new anchor.BN(1_000_000), new anchor.BN(1))
+          .accounts({
+            config: this.lsdk.configPDA,
+            mintPolicy: this.lsdk.mintPolicyPDA(mint),
+            admin: admin.publicKey,
+          })
+          .instruction(),
+      ],
+      [admin]
+    );
+    await clawback(admin, 1);
+    await assertFails(clawback(admin, 1), "WithdrawRateLimitExceeded");
+    assert.strictEqual(await lockerBalance(this, locker, mint), 2);
+  });
+
+  it("Burn items held in lockers", async function (this: CustomContext) {
+    const admin = toWeb3JsKeypair(this.lockerProgramAdmin);
+    const [user, other] = this.users;
+    const locker = this.lsdk.getLockerPDA(user.publicKey);
+    const mint = await createFundedMint(this, [user.publicKey], 10);
+    await deposit(this, user, mint, 5);
+    const [burnTa, burnBump] = await this.lsdk.withdrawSourcePDA(mint);
+    const burn = async (
+      signers: Keypair[],
+      amount: number,
+      owner: PublicKey | null = null
+    ) => {
+      const ix = await this.program.methods
+        .burnBatch([new anchor.BN(amount)], Buffer.from([burnBump]), 0)
+        .accounts({
+          config: this.lsdk.configPDA,
+          locker,
+          admin: signers[0].publicKey,
+          owner,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .remainingAccounts(
+          [mint, burnTa, this.lsdk.balancePDA(locker, mint)].map((pubkey) => ({
+            pubkey,
+            isWritable: true,
+            isSigner: false,
+          }))
+        )
+        .instruction();
+      return sendIxs(this, [ix], signers);
+    };
+
+    await burn([admin], 2);
+    assert.strictEqual(await lockerBalance(this, locker, mint), 3);
+    assert.strictEqual(
+      Number((await getMint(this.connection, mint)).supply),
+      8
+    );
+    await assertFails(burn([user], 1), "ConstraintHasOne");
+    await assertFails(burn([admin, other], 1, other.publicKey), "Unauthorized");
+    await burn([admin, user], 1, user.publicKey);
+    assert.strictEqual(await lockerBalance(this, locker, mint), 2);
+
+    const lockUntil = new anchor.BN((await chainTime(this)) + 3600);
+    await deposit(this, user, mint, 1, null, lockUntil);
+    await assertFails(burn([admin], 1), "BalanceLocked");
+
+    const emptyBatch = await this.program.methods
+      .burnBatch([], Buffer.from([]), 0)
+      .accounts({
+        config: this.lsdk.configPDA,
+        locker,
+        admin: admin.publicKey,
+        owner: null,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .instruction();
+    await assertFails(
+      sendIxs(this, [emptyBatch], [admin]),
+      "WrongRemainingAccountsSize"
+    );
+  });
+
+  it("Mint and burn program controlled currencies", async function (this: CustomContext) {
+    const admin = toWeb3JsKeypair(this.lockerProgramAdmin);
+    const user = this.users[0];
+    const locker = this.lsdk.getLockerPDA(user.publicKey);
+    const mint = await createFundedMint(this, [user.publicKey], 10);
+    await setAuthority(
+      this.connection,
+      admin,
+      mint,
+      admin,
+      AuthorityType.MintTokens,
+      this.lsdk.mintAuthorityPDA()
+    );
+    const supply = async () =>
+      Number((await getMint(this.connection, mint)).supply);
+
+    // Deposits burn instead of funding a pool, withdrawals mint.
+    await deposit(this, user, mint, 6);
+    assert.strictEqual(await supply(), 4);
+    assert.strictEqual(await lockerBalance(this, locker, mint), 6);
+    await withdraw(this, user, mint, 2);
+    assert.strictEqual(await supply(), 6);
+    assert.strictEqual(await tokenBalance(this, user.publicKey, mint), 6);
+
+    await initMintPolicy(this, mint);
+    await sendIxs(
+      this,
+      [
+        await this.program.methods
+          .setSupplyCap(new anchor.BN(7))
+          .accounts({
+            config: this.lsdk.configPDA,
+            mintPolicy: this.lsdk.mintPolicyPDA(mint),
+            admin: admin.publicKey,
+          })
+          .instruction(),
+      ],
+      [admin]
+    );
+    await assertFails(withdraw(this, user, mint, 2), "SupplyCapExceeded");
+    await withdraw(this, user, mint, 1);
+    assert.strictEqual(await supply(), 7);
+    assert.strictEqual(await lockerBalance(this, locker, mint), 3);
+  });
+
+  it("Credit rewards to many lockers", async function (this: CustomContext) {
+    const admin = toWeb3JsKeypair(this.lockerProgramAdmin);
+    const [user0, user1] = this.users;
+    const locker0 = this.lsdk.getLockerPDA(user0.publicKey);
+    const locker1 = this.lsdk.getLockerPDA(user1.publicKey);
+    const mint = await createFundedMint(this, [admin.publicKey], 10);
+    const [burnTa, burnBump] = await this.lsdk.withdrawSourcePDA(mint);
+    const creditBatch = async (
+      signer: Keypair,
+      amounts: number[],
+      lockers: PublicKey[]
+    ) => {
+      const ix = await this.program.methods
+        .creditBatch(amounts.map((amount) => new anchor.BN(amount)), burnBump)
+        .accounts({
+          config: this.lsdk.configPDA,
+          mint,
+          treasuryTa: getAssociatedTokenAddressSync(mint, admin.publicKey),
+          burnTa,
+          admin: signer.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          rent: SYSVAR_RENT_PUBKEY,
+        })
+        .remainingAccounts(
+          lockers.flatMap((locker) => [
+            { pubkey: locker, isWritable: false, isSigner: false },
+            {
+              pubkey: this.lsdk.balancePDA(locker, mint),
+              isWritable: true,
+              isSigner: false,
+            },
+          ])
+        )
+        .instruction();
+      return sendIxs(this, [ix], [signer]);
+    };
+
+    await creditBatch(admin, [3, 4], [locker0, locker1]);
+    assert.strictEqual(await lockerBalance(this, locker0, mint), 3);
+    assert.strictEqual(await lockerBalance(this, locker1, mint), 4);
+    // Credits are backed by the pool.
+    await withdraw(this, user0, mint, 3);
+    assert.strictEqual(await tokenBalance(this, user0.publicKey, mint), 3);
+
+    await assertFails(creditBatch(user0, [1], [locker0]), "ConstraintHasOne");
+    await assertFails(
+      creditBatch(admin, [1, 1], [locker0]),
+      "WrongRemainingAccountsSize"
+    );
+    await assertFails(
+      creditBatch(admin, [1], [user0.publicKey]),
+      "InvalidBalanceAccount"
+    );
+    assert.strictEqual(await tokenBalance(this, admin.publicKey, mint), 3);
+  });
+
+  it("Keep namespaces apart", async function (this: CustomContext) {
+    const rootAdmin = toWeb3JsKeypair(this.lockerProgramAdmin);
+    const namespaceAdmin = await createFundedUser(this);
+    const user = this.users[0];
+    const namespace = 1 + Math.floor(Math.random() * 1_000_000);
+    const nsSdk = new LockerSDK(
+      this.connection,
+      namespaceAdmin.publicKey,
+      this.program.programId,
+      undefined,
+      undefined,
+      undefined,
+      undefined,
+      undefined,
+      {},
+      {},
+      namespace
+    );
+    const initNamespaceConfig = (signer: Keypair) =>
+      this.program.methods
+        .initNamespaceConfig(namespace, namespaceAdmin.publicKey)
+        .accounts({
+          rootConfig: this.lsdk.configPDA,
+          config: nsSdk.configPDA,
+          admin: signer.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .instruction();
+    const unauthorizedIx = await initNamespaceConfig(namespaceAdmin);
+    await assertFails(
+      sendIxs(this, [unauthorizedIx], [namespaceAdmin]),
+      "ConstraintHasOne"
+    );
+    await sendIxs(this, [await initNamespaceConfig(rootAdmin)], [rootAdmin]);
+
+    const mint = await createFundedMint(this, [user.publicKey], 10);
+    const nsDeposit = async (amount: number, locker: PublicKey | null = null) =>
+      sendIxs(
+        this,
+        await nsSdk.depositInstruction(
+          [mint],
+          user.publicKey,
+          [new anchor.BN(amount)],
+          null,
+          null,
+          locker
+        ),
+        [user, namespaceAdmin]
+      );
+    const locker = nsSdk.getLockerPDA(user.publicKey);
+    assert.isFalse(locker.equals(this.lsdk.getLockerPDA(user.publicKey)));
+
+    // Each namespace has its own admin and lockers.
+    const rootAdminIx = await this.program.methods
+      .setLockerDailyCap(new anchor.BN(1))
+      .accounts({ config: nsSdk.configPDA, admin: rootAdmin.publicKey })
+      .instruction();
+    await assertFails(
+      sendIxs(this, [rootAdminIx], [rootAdmin]),
+      "ConstraintHasOne"
+    );
+    await assertFails(
+      nsDeposit(1, this.lsdk.getLockerPDA(user.publicKey)),
+      "InvalidLocker"
+    );
+    await nsDeposit(4);
+    assert.strictEqual(await lockerBalance(this, locker, mint), 4);
+    const [pool] = await nsSdk.withdrawSourcePDA(mint);
+    assert.strictEqual(
+      Number((await getAccount(this.connection, pool)).amount),
+      4
+    );
+
+    await sendIxs(
+      this,
+      await nsSdk.withdrawInstruction(
+        [mint],
+        user.publicKey,
+        [user.publicKey],
+        [new anchor.BN(3)]
+      ),
+      [user, namespaceAdmin]
+    );
+    assert.strictEqual(await lockerBalance(this, locker, mint), 1);
+    assert.strictEqual(await tokenBalance(this, user.publicKey, mint), 9);
+  });
+
+  it("Use several lockers per owner", async function (this: CustomContext) {
+    const [user, other] = this.users;
+    const mint = await createFundedMint(
+      this,
+      [user.publicKey, other.publicKey],
+      10
+    );
+    const mainLocker = this.lsdk.getLockerPDA(user.publicKey);
+    const locker = this.lsdk.getLockerV3PDA(user.publicKey, 1);
+    await sendIxs(
+      this,
+      [await this.lsdk.initLockerV3Instruction(user.publicKey, 1)],
+      [user]
+    );
+
+    await deposit(this, user, mint, 4, null, null, locker);
+    assert.strictEqual(await lockerBalance(this, locker, mint), 4);
+    assert.strictEqual(await lockerBalance(this, mainLocker, mint), 0);
+    await withdraw(this, user, mint, 3, null, locker);
+    assert.strictEqual(await lockerBalance(this, locker, mint), 1);
+    assert.strictEqual(await tokenBalance(this, user.publicKey, mint), 9);
+
+    // Only the owner can use the additional lockers.
+    await assertFails(
+      deposit(this, other, mint, 1, null, null, locker),
+      "Unauthorized"
+    );
+    await assertFails(
+      withdraw(this, other, mint, 1, null, locker),
+      "Unauthorized"
+    );
+    assert.strictEqual(await lockerBalance(this, locker, mint), 1);
+  });
+
+  it("Share guild lockers between members", async function (this: CustomContext) {
+    const admin = toWeb3JsKeypair(this.lockerProgramAdmin);
+    const [member0, member1] = this.users;
+    const outsider = await createFundedUser(this);
+    const mint = await createFundedMint(
+      this,
+      [member0.publicKey, outsider.publicKey],
+      10
+    );
+    const [burnTa, burnBump] = await this.lsdk.withdrawSourcePDA(mint);
+    const initGuild = async (id: anchor.BN, threshold: number) => {
+      const guild = this.lsdk.guildPDA(id);
+      const ix = await this.program.methods
+        .initGuild(id, [member0.publicKey, member1.publicKey], threshold)
+        .accounts({
+          config: this.lsdk.configPDA,
+          guild,
+          locker: this.lsdk.getLockerPDA(guild),
+          payer: member0.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .instruction();
+      await sendIxs(this, [ix], [member0]);
+      return guild;
+    };
+
+    const id = new anchor.BN(Date.now());
+    await assertFails(initGuild(id.addn(1), 3), "InvalidGuild");
+    const guild = await initGuild(id, 2);
+    const locker = this.lsdk.getLockerPDA(guild);
+    const pooledAccounts = (user: PublicKey, recipient: PublicKey) => {
+      const [vaultTa, vaultBump] = PublicKey.findProgramAddressSync(
+        [mint.toBuffer(), user.toBuffer()],
+        this.program.programId
+      );
+      const accounts = [
+        mint,
+        getAssociatedTokenAddressSync(mint, recipient, true),
+        vaultTa,
+        burnTa,
+        this.lsdk.balancePDA(locker, mint),
+      ];
+      return { accounts, vaultBump };
+    };
+    const guildDeposit = async (depositor: Keypair, amount: number) => {
+      const { space } = await this.program.account.locker.fetch(locker);
+      const { accounts, vaultBump } = pooledAccounts(
+        depositor.publicKey,
+        depositor.publicKey
+      );
+      const ix = await this.program.methods
+        .guildDepositBatch(
+          [new anchor.BN(amount)],
+          Buffer.from([vaultBump]),
+          Buffer.from([burnBump]),
+          0,
+          space,
+          null
+        )
+        .accounts({
+          config: this.lsdk.configPDA,
+          guild,
+          locker,
+          depositor: depositor.publicKey,
+          admin: admin.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          rent: SYSVAR_RENT_PUBKEY,
+          treasury: this.lsdk.treasuryPDA(),
+          instructions: SYSVAR_INSTRUCTIONS_PUBKEY,
+        })
+        .remainingAccounts(
+          accounts.map((pubkey) => ({
+            pubkey,
+            isWritable: true,
+            isSigner: false,
+          }))
+        )
+        .instruction();
+      return sendIxs(this, [ix], [depositor, admin]);
+    };
+    const guildWithdraw = async (
+      withdrawer: Keypair,
+      approvers: Keypair[],
+      amount: number
+    ) => {
+      const { space } = await this.program.account.locker.fetch(locker);
+      const { accounts, vaultBump } = pooledAccounts(
+        withdrawer.publicKey,
+        withdrawer.publicKey
+      );
+      // Withdrawals pay out to the withdrawing member, chunks are [mint,
+      // recipient ta, vault, vault owner, pool, balance, mint policy,
+      // treasury ta].
+      accounts.splice(3, 0, withdrawer.publicKey);
+      accounts.push(
+        this.lsdk.mintPolicyPDA(mint),
+        this.lsdk.treasuryTaPDA(mint)
+      );
+      const ix = await this.program.methods
+        .guildWithdrawBatch(
+          [new anchor.BN(amount)],
+          Buffer.from([vaultBump]),
+          Buffer.from([burnBump]),
+          0,
+          approvers.length,
+          space,
+          null
+        )
+        .accounts({
+          withdraw: {
+            config: this.lsdk.configPDA,
+            locker,
+            delegate: null,
+            admin: admin.publicKey,
+            userTaOwner: withdrawer.publicKey,
+            lockerOwner: guild,
+            vaultTaOwner: withdrawer.publicKey,
+            systemProgram: SystemProgram.programId,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+            rent: SYSVAR_RENT_PUBKEY,
+            treasury: this.lsdk.treasuryPDA(),
+            instructions: SYSVAR_INSTRUCTIONS_PUBKEY,
+          },
+          guild,
+        })
+        .remainingAccounts([
+          ...approvers.map((approver) => ({
+            pubkey: approver.publicKey,
+            isWritable: false,
+            isSigner: true,
+          })),
+          ...accounts.map((pubkey) => ({
+            pubkey,
+            isWritable: true,
+            isSigner: false,
+          })),
+        ])
+        .instruction();
+      const signers = [withdrawer, admin, ...approvers].filter(
+        (signer, index, all) => all.indexOf(signer) === index
+      );
+      return sendIxs(this, [ix], signers);
+    };
+
+    await assertFails(guildDeposit(outsider, 1), "Unauthorized");
+    await guildDeposit(member0, 5);
+    assert.strictEqual(await lockerBalance(this, locker, mint), 5);
+
+    await assertFails(
+      guildWithdraw(member0, [member0], 1),
+      "GuildThresholdNotMet"
+    );
+    await assertFails(
+      guildWithdraw(outsider, [member0, member1], 1),
+      "Unauthorized"
+    );
+    await guildWithdraw(member0, [member0, member1], 3);
+    assert.strictEqual(await lockerBalance(this, locker, mint), 2);
+    assert.strictEqual(await tokenBalance(this, member0.publicKey, mint), 8);
+  });
+
+  it("Withdraw through scoped delegates", async function (this: CustomContext) {
+    const admin = toWeb3JsKeypair(this.lockerProgramAdmin);
+    const user = this.users[0];
+    const withdrawKey = await createFundedUser(this);
+    const depositKey = await createFundedUser(this);
+    const locker = this.lsdk.getLockerPDA(user.publicKey);
+    const mint = await createFundedMint(this, [user.publicKey], 10);
+    await deposit(this, user, mint, 6);
+    const [burnTa, burnBump] = await this.lsdk.withdrawSourcePDA(mint);
+    const expiry = new anchor.BN((await chainTime(this)) + 3600);
+    const createDelegate = async (delegate: PublicKey, operations: number) => {
+      const ix = await this.program.methods
+        .createDelegate(
+          delegate,
+          operations,
+          [{ mint, amount: new anchor.BN(3) }],
+          expiry
+        )
+        .accounts({
+          locker,
+          delegateAccount: this.lsdk.delegatePDA(locker, delegate),
+          owner: user.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .instruction();
+      return sendIxs(this, [ix], [user]);
+    };
+    const delegateWithdraw = async (delegate: Keypair, amount: number) => {
+      const { space } = await this.program.account.locker.fetch(locker);
+      const [vaultTa, vaultBump] = PublicKey.findProgramAddressSync(
+        [mint.toBuffer(), delegate.publicKey.toBuffer()],
+        this.program.programId
+      );
+      const ix = await this.program.methods
+        .withdrawV2Batch(
+          [new anchor.BN(amount)],
+          Buffer.from([vaultBump]),
+          Buffer.from([burnBump]),
+          0,
+          space,
+          null
+        )
+        .accounts({
+          config: this.lsdk.configPDA,
+          locker,
+          delegate: this.lsdk.delegatePDA(locker, delegate.publicKey),
+          admin: admin.publicKey,
+          userTaOwner: delegate.publicKey,
+          lockerOwner: user.publicKey,
+          vaultTaOwner: delegate.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          rent: SYSVAR_RENT_PUBKEY,
+          treasury: this.lsdk.treasuryPDA(),
+          instructions: SYSVAR_INSTRUCTIONS_PUBKEY,
+        })
+        .remainingAccounts(
+          [
+            mint,
+            getAssociatedTokenAddressSync(mint, user.publicKey),
+            vaultTa,
+            delegate.publicKey,
+            burnTa,
+            this.lsdk.balancePDA(locker, mint),
+            this.lsdk.mintPolicyPDA(mint),
+            this.lsdk.treasuryTaPDA(mint),
+          ].map((pubkey) => ({ pubkey, isWritable: true, isSigner: false }))
+        )
+        .instruction();
+      return sendIxs(this, [ix], [delegate, admin]);
+    };
+
+    await createDelegate(withdrawKey.publicKey, 2);
+    await assertFails(
+      delegateWithdraw(withdrawKey, 4),
+      "DelegateAllowanceExceeded"
+    );
+    // Delegates withdraw to the owner, never to themselves.
+    await delegateWithdraw(withdrawKey, 2);
+    assert.strictEqual(await lockerBalance(this, locker, mint), 4);
+    assert.strictEqual(await tokenBalance(this, user.publicKey, mint), 6);
+    assert.strictEqual(
+      await tokenBalance(this, withdrawKey.publicKey, mint),
+      0
+    );
+    await assertFails(
+      delegateWithdraw(withdrawKey, 2),
+      "DelegateAllowanceExceeded"
+    );
+
+    await createDelegate(depositKey.publicKey, 1);
+    await assertFails(delegateWithdraw(depositKey, 1), "Unauthorized");
+
+    await sendIxs(
+      this,
+      [
+        await this.program.methods
+          .revokeDelegate()
+          .accounts({
+            locker,
+            delegate: this.lsdk.delegatePDA(locker, withdrawKey.publicKey),
+            owner: user.publicKey,
+          })
+          .instruction(),
+      ],
+      [user]
+    );
+    await assertFails(
+      delegateWithdraw(withdrawKey, 1),
+      "AccountNotInitialized"
+    );
+  });
+
+  it("Recover lockers to a new wallet", async function (this: CustomContext) {
+    const owner = await createFundedUser(this);
+    const recoveryKey = await createFundedUser(this);
+    const newOwner = await createFundedUser(this);
+    const locker = this.lsdk.getLockerPDA(owner.publicKey);
+    const newLocker = this.lsdk.getLockerPDA(newOwner.publicKey);
+    const mint = await createFundedMint(this, [owner.publicKey], 10);
+    const other = await createFundedMint(this, [owner.publicKey], 10);
+    await deposit(this, owner, mint, 5);
+    await deposit(this, owner, other, 3);
+    await initLocker(this, newOwner);
+    const [recovery] = PublicKey.findProgramAddressSync(
+      [anchor.utils.bytes.utf8.encode("recovery"), locker.toBuffer()],
+      this.program.programId
+    );
+    await sendIxs(
+      this,
+      [
+        await this.program.methods
+          .setRecovery(recoveryKey.publicKey, new anchor.BN(3))
+          .accounts({
+            locker,
+            recovery,
+            owner: owner.publicKey,
+            systemProgram: SystemProgram.programId,
+          })
+          .instruction(),
+      ],
+      [owner]
+    );
+    const initiateRecovery = async (signer: Keypair) =>
+      sendIxs(
+        this,
+        [
+          await this.program.methods
+            .initiateRecovery(newOwner.publicKey)
+            .accounts({ recovery, recoveryKey: signer.publicKey })
+            .instruction(),
+        ],
+        [signer]
+      );
+    const recoverLocker = async (mints: PublicKey[]) =>
+      sendIxs(
+        this,
+        [
+          await this.program.methods
+            .recoverLocker()
+            .accounts({
+              config: this.lsdk.configPDA,
+              locker,
+              recovery,
+              newLocker,
+              recoveryKey: recoveryKey.publicKey,
+              systemProgram: SystemProgram.programId,
+            })
+            .remainingAccounts(
+              mints
+                .flatMap((mint) => [
+                  mint,
+                  this.lsdk.balancePDA(locker, mint),
+                  this.lsdk.balancePDA(newLocker, mint),
+                  this.lsdk.rentalPDA(locker, mint),
+                ])
+                .map((pubkey) => ({
+                  pubkey,
+                  isWritable: true,
+                  isSigner: false,
+                }))
+            )
+            .instruction(),
+        ],
+        [recoveryKey]
+      );
+    const finalizeRecovery = async () =>
+      sendIxs(
+        this,
+        [
+          await this.program.methods
+            .finalizeRecovery()
+            .accounts({
+              config: this.lsdk.configPDA,
+              locker,
+              recovery,
+              newLocker,
+              recoveryKey: recoveryKey.publicKey,
+            })
+            .instruction(),
+        ],
+        [recoveryKey]
+      );
+
+    await assertFails(initiateRecovery(owner), "ConstraintHasOne");
+    await assertFails(recoverLocker([mint]), "RecoveryNotReady");
+    await assertFails(finalizeRecovery(), "RecoveryNotReady");
+
+    // The owner can cancel a pending recovery.
+    await initiateRecovery(recoveryKey);
+    await sendIxs(
+      this,
+      [
+        await this.program.methods
+          .cancelRecovery()
+          .accounts({ locker, recovery, owner: owner.publicKey })
+          .instruction(),
+      ],
+      [owner]
+    );
+    await sleep(5000);
+    await assertFails(recoverLocker([mint]), "RecoveryNotReady");
+
+    await initiateRecovery(recoveryKey);
+    await assertFails(recoverLocker([mint]), "RecoveryNotReady");
+    await sleep(5000);
+    const { space } = await this.program.account.locker.fetch(locker);
+    await recoverLocker([mint]);
+    await recoverLocker([other]);
+    assert.strictEqual(await lockerBalance(this, locker, mint), 0);
+    assert.strictEqual(await lockerBalance(this, newLocker, mint), 5);
+    assert.strictEqual(await lockerBalance(this, newLocker, other), 3);
+
+    // The recovery stays open across batches, finalizing it closes it and
+    // moves the nonces once.
+    assert.isNotNull(await this.connection.getAccountInfo(recovery));
+    assert.isTrue(
+      (await this.program.account.locker.fetch(locker)).space.eq(space)
+    );
+    await finalizeRecovery();
+    assert.isNull(await this.connection.getAccountInfo(recovery));
+    for (const lockerAccount of [locker, newLocker]) {
+      assert.isTrue(
+        (await this.program.account.locker.fetch(lockerAccount)).space.eq(
+          space.addn(1)
+        )
+      );
+    }
+    await withdraw(this, newOwner, mint, 5);
+    assert.strictEqual(await tokenBalance(this, newOwner.publicKey, mint), 5);
+  });
+
+  it("Rent items between lockers", async function (this: CustomContext) {
+    const lender = this.users[0];
+    const borrower = await createFundedUser(this);
+    const recoveryKey = await createFundedUser(this);
+    const newOwner = await createFundedUser(this);
+    await initLocker(this, borrower);
+    await initLocker(this, newOwner);
+    const lenderLocker = this.lsdk.getLockerPDA(lender.publicKey);
+    const borrowerLocker = this.lsdk.getLockerPDA(borrower.publicKey);
+    const mint = await createFundedMint(this, [lender.publicKey], 10);
+    await deposit(this, lender, mint, 5);
+    const rental = this.lsdk.rentalPDA(lenderLocker, mint);
+    const createRental = async (expiry: number) =>
+      sendIxs(
+        this,
+        [
+          await this.program.methods
+            .createRental(new anchor.BN(2), new anchor.BN(expiry))
+            .accounts({
+              config: this.lsdk.configPDA,
+              lenderLocker,
+              borrowerLocker,
+              mint,
+              rental,
+              lenderBalance: this.lsdk.balancePDA(lenderLocker, mint),
+              borrowerBalance: this.lsdk.balancePDA(borrowerLocker, mint),
+              lenderOwner: lender.publicKey,
+              borrowerOwner: borrower.publicKey,
+              systemProgram: SystemProgram.programId,
+            })
+            .instruction(),
+        ],
+        [lender, borrower]
+      );
+    const settleRental = async () =>
+      sendIxs(
+        this,
+        [
+          await this.program.methods
+            .settleRental()
+            .accounts({
+              rental,
+              lenderLocker,
+              borrowerLocker,
+              lenderBalance: this.lsdk.balancePDA(lenderLocker, mint),
+              borrowerBalance: this.lsdk.balancePDA(borrowerLocker, mint),
+              lenderOwner: lender.publicKey,
+            })
+            .instruction(),
+        ],
+        [borrower]
+      );
+
+    const now = await chainTime(this);
+    await assertFails(createRental(now - 10), "OperationExpired");
+    await createRental(now + 4);
+    assert.strictEqual(await lockerBalance(this, lenderLocker, mint), 3);
+    assert.strictEqual(await lockerBalance(this, borrowerLocker, mint), 2);
+    // Rented items can't leave the borrower's locker.
+    await assertFails(withdraw(this, borrower, mint, 1), "BalanceLocked");
+    await assertFails(settleRental(), "RentalNotExpired");
+
+    // Nor be moved away by a recovery, and the lender can't abandon the
+    // locker they come back to.
+    const newLocker = this.lsdk.getLockerPDA(newOwner.publicKey);
+    const recover = async (owner: Keypair, locker: PublicKey) => {
+      const [recovery] = PublicKey.findProgramAddressSync(
+        [anchor.utils.bytes.utf8.encode("recovery"), locker.toBuffer()],
+        this.program.programId
+      );
+      await sendIxs(
+        this,
+        [
+          await this.program.methods
+            .setRecovery(recoveryKey.publicKey, new anchor.BN(0))
+            .accounts({
+              locker,
+              recovery,
+              owner: owner.publicKey,
+              systemProgram: SystemProgram.programId,
+            })
+            .instruction(),
+          await this.program.methods
+            .initiateRecovery(newOwner.publicKey)
+            .accounts({ recovery, recoveryKey: recoveryKey.publicKey })
+            .instruction(),
+        ],
+        [owner, recoveryKey]
+      );
+      const recoverIx = await this.program.methods
+        .recoverLocker()
+        .accounts({
+          config: this.lsdk.configPDA,
+          locker,
+          recovery,
+          newLocker,
+          recoveryKey: recoveryKey.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .remainingAccounts(
+          [
+            mint,
+            this.lsdk.balancePDA(locker, mint),
+            this.lsdk.balancePDA(newLocker, mint),
+            this.lsdk.rentalPDA(locker, mint),
+          ].map((pubkey) => ({ pubkey, isWritable: true, isSigner: false }))
+        )
+        .instruction();
+      try {
+        await sendIxs(this, [recoverIx], [recoveryKey]);
+      } finally {
+        await sendIxs(
+          this,
+          [
+            await this.program.methods
+              .cancelRecovery()
+              .accounts({ locker, recovery, owner: owner.publicKey })
+              .instruction(),
+          ],
+          [owner]
+        );
+      }
+    };
+    await assertFails(recover(borrower, borrowerLocker), "RentedBalance");
+    await assertFails(recover(lender, lenderLocker), "LentBalance");
+
+    await sleep(6000);
+    await settleRental();
+    assert.strictEqual(await lockerBalance(this, lenderLocker, mint), 5);
+    assert.strictEqual(await lockerBalance(this, borrowerLocker, mint), 0);
+    const { lockUntil } = await this.program.account.balance.fetch(
+      this.lsdk.balancePDA(borrowerLocker, mint)
+    );
+    assert.strictEqual(lockUntil.toNumber(), 0);
+  });
+
+  it("Read locker balances", async function (this: CustomContext) {
+    const user = this.users[0];
+    const locker = this.lsdk.getLockerPDA(user.publicKey);
+    const held = await createFundedMint(this, [user.publicKey], 10);
+    const absent = await createFundedMint(this, [user.publicKey], 10);
+    const lockUntil = new anchor.BN((await chainTime(this)) + 3600);
+    await deposit(this, user, held, 3, null, lockUntil);
+    const getBalances = (mints: PublicKey[], balances: PublicKey[]) =>
+      this.program.methods
+        .getBalances(mints)
+        .accounts({ locker })
+        .remainingAccounts(
+          balances.map((pubkey) => ({
+            pubkey,
+            isWritable: false,
+            isSigner: false,
+          }))
+        )
+        .view();
+
+    const views = await getBalances(
+      [held, absent],
+      [
+        this.lsdk.balancePDA(locker, held),
+        this.lsdk.balancePDA(locker, absent),
+      ]
+    );
+    assert.strictEqual(views.length, 2);
+    assert.isTrue(views[0].mint.equals(held));
+    assert.strictEqual(views[0].amount.toNumber(), 3);
+    assert.strictEqual(views[0].lockUntil.toNumber(), lockUntil.toNumber());
+    // Entries never created read as empty.
+    assert.isTrue(views[1].mint.equals(absent));
+    assert.strictEqual(views[1].amount.toNumber(), 0);
+
+    await assertFails(
+      getBalances([held], [this.lsdk.balancePDA(locker, absent)]),
+      "InvalidBalanceAccount"
+    );
+    const mints = [...Array(21)].map(() => Keypair.generate().publicKey);
+    await assertFails(
+      getBalances(
+        mints,
+        mints.map((mint) => this.lsdk.balancePDA(locker, mint))
+      ),
+      "TooManyBalances"
+    );
+  });
+
+  it("Guard against CPI callers", async function (this: CustomContext) {
+    const admin = toWeb3JsKeypair(this.lockerProgramAdmin);
+    const user = this.users[0];
+    const mint = await createFundedMint(this, [user.publicKey], 10);
+    const setCpiGuard = async (
+      signer: Keypair,
+      guard: number,
+      allowlist: PublicKey[] = []
+    ) =>
+      sendIxs(
+        this,
+        [
+          await this.program.methods
+            .setCpiGuard(guard, allowlist)
+            .accounts({ config: this.lsdk.configPDA, admin: signer.publicKey })
+            .instruction(),
+        ],
+        [signer]
+      );
+
+    await assertFails(setCpiGuard(user, 2), "ConstraintHasOne");
+    await assertFails(setCpiGuard(admin, 3), "InvalidCpiGuard");
+    const allowlist = [...Array(9)].map(() => Keypair.generate().publicKey);
+    await assertFails(setCpiGuard(admin, 1, allowlist), "InvalidCpiGuard");
+
+    // Deposits made by the caller program reach casier through CPI.
+    const caller = new Program<CasierCaller>(
+      anchor.workspace.CasierCaller.idl,
+      anchor.workspace.CasierCaller.programId,
+      this.program.provider
+    );
+    const callerDeposit = async (amount: number) => {
+      const ixs = await this.lsdk.depositInstruction(
+        [mint],
+        user.publicKey,
+        [new anchor.BN(amount)]
+      );
+      const forwarded = await Promise.all(
+        ixs.map((ix) =>
+          ix.programId.equals(this.program.programId)
+            ? caller.methods
+                .forward(ix.data)
+                .accounts({ casier: this.program.programId })
+                .remainingAccounts(ix.keys)
+                .instruction()
+            : ix
+        )
+      );
+      return sendIxs(this, forwarded, [user, admin]);
+    };
+
+    // Top level calls go through whatever the guard, only CPI is restricted.
+    try {
+      await callerDeposit(1);
+
+      await setCpiGuard(admin, 1, allowlist.slice(0, 8));
+      const config = await this.program.account.config.fetch(
+        this.lsdk.configPDA
+      );
+      assert.strictEqual(config.cpiGuard, 1);
+      assert.strictEqual(config.cpiAllowlist.length, 8);
+      await assertFails(callerDeposit(1), "UnauthorizedCaller");
+      await deposit(this, user, mint, 2);
+
+      await setCpiGuard(admin, 1, [...allowlist.slice(0, 7), caller.programId]);
+      await callerDeposit(1);
+
+      await setCpiGuard(admin, 2);
+      await assertFails(callerDeposit(1), "UnauthorizedCaller");
+      await withdraw(this, user, mint, 1);
+    } finally {
+      await setCpiGuard(admin, 0);
+    }
+    assert.strictEqual(
+      await lockerBalance(this, this.lsdk.getLockerPDA(user.publicKey), mint),
+      3
+    );
+  });
+
+  it("Charge and sweep fees", async function (this: CustomContext) {
+    const admin = toWeb3JsKeypair(this.lockerProgramAdmin);
+    const [user, destination] = this.users;
+    const mint = await createFundedMint(this, [user.publicKey], 20);
+    const treasury = this.lsdk.treasuryPDA();
+    const treasuryTa = this.lsdk.treasuryTaPDA(mint);
+    const rentMin = await this.connection.getMinimumBalanceForRentExemption(0);
+    const lamports = (account: PublicKey) =>
+      this.connection.getBalance(account);
+    const setFees = async (depositFee: number, withdrawFee: number) =>
+      sendIxs(
+        this,
+        [
+          await this.program.methods
+            .setFees(new anchor.BN(depositFee), new anchor.BN(withdrawFee))
+            .accounts({ config: this.lsdk.configPDA, admin: admin.publicKey })
+            .instruction(),
+        ],
+        [admin]
+      );
+    const setWithdrawFee = async (bps: number) =>
+      sendIxs(
+        this,
+        [
+          await this.program.methods
+            .setWithdrawFee(new anchor.BN(0), bps)
+            .accounts({
+              config: this.lsdk.configPDA,
+              mintPolicy: this.lsdk.mintPolicyPDA(mint),
+              admin: admin.publicKey,
+            })
+            .instruction(),
+        ],
+        [admin]
+      );
+
+    await setFees(1000, 2000);
+    try {
+      // The first fee also makes the treasury rent exempt.
+      let expected = Math.max(await lamports(treasury), rentMin) + 1000;
+      await deposit(this, user, mint, 20);
+      assert.strictEqual(await lamports(treasury), expected);
+      await withdraw(this, user, mint, 1);
+      expected += 2000;
+      assert.strictEqual(await lamports(treasury), expected);
+
+      // A share of the withdrawn tokens goes to the treasury token account.
+      await initMintPolicy(this, mint);
+      await assertFails(setWithdrawFee(1001), "InvalidFee");
+      await setWithdrawFee(1000);
+      await withdraw(this, user, mint, 10);
+      expected += 2000;
+      assert.strictEqual(await lamports(treasury), expected);
+      assert.strictEqual(await tokenBalance(this, user.publicKey, mint), 10);
+      assert.strictEqual(
+        Number((await getAccount(this.connection, treasuryTa)).amount),
+        1
+      );
+    } finally {
+      await setFees(0, 0);
+    }
+
+    const destinationTa = await getOrCreateAssociatedTokenAccount(
+      this.connection,
+      admin,
+      mint,
+      destination.publicKey
+    );
+    const sweepFees = async (signer: Keypair) =>
+      sendIxs(
+        this,
+        [
+          await this.program.methods
+            .sweepFees()
+            .accounts({
+              config: this.lsdk.configPDA,
+              treasury,
+              destination: destination.publicKey,
+              admin: signer.publicKey,
+              systemProgram: SystemProgram.programId,
+              tokenProgram: TOKEN_PROGRAM_ID,
+            })
+            .remainingAccounts(
+              [treasuryTa, destinationTa.address].map((pubkey) => ({
+                pubkey,
+                isWritable: true,
+                isSigner: false,
+              }))
+            )
+            .instruction(),
+        ],
+        [signer]
+      );
+    await assertFails(sweepFees(user), "ConstraintHasOne");
+    const treasuryBefore = await lamports(treasury);
+    const destinationBefore = await lamports(destination.publicKey);
+    await sweepFees(admin);
+    // The treasury keeps its rent exempt minimum.
+    assert.strictEqual(await lamports(treasury), rentMin);
+    assert.strictEqual(
+      await lamports(destination.publicKey),
+      destinationBefore + treasuryBefore - rentMin
+    );
+    assert.strictEqual(
+      await tokenBalance(this, destination.publicKey, mint),
+      1
+    );
+  });
+
+  it("Reclaim rent of empty token accounts", async function (this: CustomContext) {
+    const admin = toWeb3JsKeypair(this.lockerProgramAdmin);
+    const user = this.users[0];
+    const rentRecipient = Keypair.generate().publicKey;
+    const mint = await createFundedMint(this, [user.publicKey], 10);
+    const [pool, poolBump] = await this.lsdk.withdrawSourcePDA(mint);
+    const [vault, vaultBump] = PublicKey.findProgramAddressSync(
+      [mint.toBuffer(), user.publicKey.toBuffer()],
+      this.program.programId
+    );
+    const setRentRecipient = async (recipient: PublicKey) =>
+      sendIxs(
+        this,
+        [
+          await this.program.methods
+            .setRentRecipient(recipient)
+            .accounts({ config: this.lsdk.configPDA, admin: admin.publicKey })
+            .instruction(),
+        ],
+        [admin]
+      );
+    const reclaimRent = async (
+      signer: Keypair,
+      recipient: PublicKey,
+      accounts: {
+        tokenAccount: PublicKey;
+        bump: number;
+        owner: PublicKey | null;
+      }[]
+    ) =>
+      sendIxs(
+        this,
+        [
+          await this.program.methods
+            .reclaimRent(
+              Buffer.from(accounts.map(({ bump }) => bump)),
+              accounts.map(({ owner }) => owner)
+            )
+            .accounts({
+              config: this.lsdk.configPDA,
+              rentRecipient: recipient,
+              admin: signer.publicKey,
+              tokenProgram: TOKEN_PROGRAM_ID,
+            })
+            .remainingAccounts(
+              accounts.flatMap(({ tokenAccount }) => [
+                { pubkey: mint, isWritable: false, isSigner: false },
+                { pubkey: tokenAccount, isWritable: true, isSigner: false },
+              ])
+            )
+            .instruction(),
+        ],
+        [signer]
+      );
+    const poolAccount = { tokenAccount: pool, bump: poolBump, owner: null };
+    const vaultAccount = {
+      tokenAccount: vault,
+      bump: vaultBump,
+      owner: user.publicKey,
+    };
+
+    await deposit(this, user, mint, 3);
+    await assertFails(
+      reclaimRent(admin, PublicKey.default, [poolAccount]),
+      "RentRecipientNotSet"
+    );
+    await setRentRecipient(rentRecipient);
+    try {
+      await assertFails(
+        reclaimRent(admin, rentRecipient, [poolAccount]),
+        "InvalidVault"
+      );
+      // Withdrawing empties the pool and leaves an empty legacy vault.
+      await withdraw(this, user, mint, 3);
+      await assertFails(
+        reclaimRent(user, rentRecipient, [poolAccount, vaultAccount]),
+        "ConstraintHasOne"
+      );
+      const rent =
+        (await this.connection.getBalance(pool)) +
+        (await this.connection.getBalance(vault));
+      await reclaimRent(admin, rentRecipient, [poolAccount, vaultAccount]);
+      assert.isNull(await this.connection.getAccountInfo(pool));
+      assert.isNull(await this.connection.getAccountInfo(vault));
+      assert.strictEqual(await this.connection.getBalance(rentRecipient), rent);
+    } finally {
+      await setRentRecipient(PublicKey.default);
+    }
+  });
+
+  it("Migrate legacy vaults into the pool", async function (this: CustomContext) {
+    const admin = toWeb3JsKeypair(this.lockerProgramAdmin);
+    const user = this.users[0];
+    const locker = this.lsdk.getLockerPDA(user.publicKey);
+    const mint = await createFundedMint(this, [user.publicKey], 10);
+    const [pool, poolBump] = await this.lsdk.withdrawSourcePDA(mint);
+    const [vault, vaultBump] = PublicKey.findProgramAddressSync(
+      [mint.toBuffer(), user.publicKey.toBuffer()],
+      this.program.programId
+    );
+    const userTa = getAssociatedTokenAddressSync(mint, user.publicKey);
+    // Withdrawing leaves an empty legacy vault, filled here as older
+    // versions of the program did.
+    const fillVault = async (amount: number) => {
+      await withdraw(this, user, mint, await lockerBalance(this, locker, mint));
+      await sendIxs(
+        this,
+        [createTransferInstruction(userTa, vault, user.publicKey, amount)],
+        [user]
+      );
+    };
+    const migrate = async (signer: Keypair, lockerAccount: PublicKey) =>
+      sendIxs(
+        this,
+        [
+          await this.program.methods
+            .migrateLegacyVaults(
+              Buffer.from([vaultBump]),
+              Buffer.from([poolBump])
+            )
+            .accounts({
+              config: this.lsdk.configPDA,
+              admin: signer.publicKey,
+              systemProgram: SystemProgram.programId,
+              tokenProgram: TOKEN_PROGRAM_ID,
+              rent: SYSVAR_RENT_PUBKEY,
+            })
+            .remainingAccounts(
+              [
+                mint,
+                lockerAccount,
+                user.publicKey,
+                vault,
+                pool,
+                this.lsdk.balancePDA(lockerAccount, mint),
+              ].map((pubkey, index) => ({
+                pubkey,
+                isWritable: index > 1,
+                isSigner: false,
+              }))
+            )
+            .instruction(),
+        ],
+        [signer]
+      );
+
+    await deposit(this, user, mint, 4);
+    await fillVault(3);
+    await assertFails(
+      migrate(admin, this.lsdk.getLockerPDA(this.users[1].publicKey)),
+      "InvalidLocker"
+    );
+    await assertFails(migrate(user, locker), "ConstraintHasOne");
+    await migrate(admin, locker);
+    assert.isNull(await this.connection.getAccountInfo(vault));
+    assert.strictEqual(await lockerBalance(this, locker, mint), 3);
+    assert.strictEqual(
+      Number((await getAccount(this.connection, pool)).amount),
+      3
+    );
+
+    // A deposit drains the legacy vault and credits it as well.
+    await fillVault(2);
+    await deposit(this, user, mint, 1);
+    assert.isNull(await this.connection.getAccountInfo(vault));
+    assert.strictEqual(await lockerBalance(this, locker, mint), 3);
+    assert.strictEqual(
+      Number((await getAccount(this.connection, pool)).amount),
+      3
+    );
+    assert.strictEqual(await tokenBalance(this, user.publicKey, mint), 7);
+  });
+
+  it("Refuse deposits into a token account posing as the pool", async function (this: CustomContext) {
+    const admin = toWeb3JsKeypair(this.lockerProgramAdmin);
+    const user = await createFundedUser(this);
+    const mint = await createFundedMint(this, [user.publicKey], 5);
+    const [pool] = await this.lsdk.withdrawSourcePDA(mint);
+    // Owns itself like the pool does, but sits at a keypair address.
+    const fakePoolKeypair = Keypair.generate();
+    const fakePool = await createAccount(
+      this.connection,
+      user,
+      mint,
+      fakePoolKeypair.publicKey,
+      fakePoolKeypair
+    );
+    const ixs = await this.lsdk.depositInstruction([mint], user.publicKey, [
+      new anchor.BN(5),
+    ]);
+    for (const ix of ixs) {
+      for (const key of ix.keys) {
+        if (key.pubkey.equals(pool)) {
+          key.pubkey = fakePool;
+        }
+      }
+    }
+
+    await assertFails(sendIxs(this, ixs, [user, admin]), "InvalidVault");
+    assert.strictEqual(await tokenBalance(this, user.publicKey, mint), 5);
+    assert.strictEqual(
+      await lockerBalance(this, this.lsdk.getLockerPDA(user.publicKey), mint),
+      0
+    );
+  });
+
+  it("Backfill balances predating the ledger", async function (this: CustomContext) {
+    const admin = toWeb3JsKeypair(this.lockerProgramAdmin);
+    const user = await createFundedUser(this);
+    const locker = this.lsdk.getLockerPDA(user.publicKey);
+    const mint = await createFundedMint(this, [user.publicKey], 10);
+    const userTa = getAssociatedTokenAddressSync(mint, user.publicKey);
+    const [pool, poolBump] = await this.lsdk.withdrawSourcePDA(mint);
+    const [vault] = PublicKey.findProgramAddressSync(
+      [mint.toBuffer(), user.publicKey.toBuffer()],
+      this.program.programId
+    );
+    const backfill = async (
+      signer: Keypair,
+      amount: number,
+      config: PublicKey = this.lsdk.configPDA
+    ) =>
+      sendIxs(
+        this,
+        [
+          await this.program.methods
+            .backfillBalances([new anchor.BN(amount)], poolBump)
+            .accounts({
+              config,
+              mint,
+              burnTa: pool,
+              admin: signer.publicKey,
+              systemProgram: SystemProgram.programId,
+            })
+            .remainingAccounts(
+              [locker, this.lsdk.balancePDA(locker, mint)].map((pubkey) => ({
+                pubkey,
+                isWritable: true,
+                isSigner: false,
+              }))
+            )
+            .instruction(),
+        ],
+        [signer]
+      );
+
+    // Items sent to the pool by earlier program versions have no ledger entry.
+    await deposit(this, user, mint, 1);
+    await sendIxs(
+      this,
+      [createTransferInstruction(userTa, pool, user.publicKey, 4)],
+      [user]
+    );
+    await assertFails(
+      withdraw(this, user, mint, 5),
+      "InsufficientLockerBalance"
+    );
+    await assertFails(backfill(user, 4), "ConstraintHasOne");
+    await assertFails(backfill(admin, 6), "InsufficientFunds");
+    await backfill(admin, 4);
+    assert.strictEqual(await lockerBalance(this, locker, mint), 5);
+    await assertFails(backfill(admin, 4), "BalanceAlreadyBackfilled");
+    await withdraw(this, user, mint, 5);
+    assert.strictEqual(await tokenBalance(this, user.publicKey, mint), 10);
+
+    // Holdings left in a legacy vault are credited when withdrawing.
+    await sendIxs(
+      this,
+      [createTransferInstruction(userTa, vault, user.publicKey, 3)],
+      [user]
+    );
+    await withdraw(this, user, mint, 3);
+    assert.strictEqual(await lockerBalance(this, locker, mint), 0);
+    assert.strictEqual(await tokenBalance(this, user.publicKey, mint), 10);
+    assert.strictEqual(
+      Number((await getAccount(this.connection, vault)).amount),
+      0
+    );
+
+    // Once closed, the admin can no longer credit the pool holdings.
+    const namespaceAdmin = await createFundedUser(this);
+    const namespace = 1 + Math.floor(Math.random() * 1_000_000);
+    const nsSdk = new LockerSDK(
+      this.connection,
+      namespaceAdmin.publicKey,
+      this.program.programId,
+      undefined,
+      undefined,
+      undefined,
+      undefined,
+      undefined,
+      {},
+      {},
+      namespace
+    );
+    await sendIxs(
+      this,
+      [
+        await this.program.methods
+          .initNamespaceConfig(namespace, namespaceAdmin.publicKey)
+          .accounts({
+            rootConfig: this.lsdk.configPDA,
+            config: nsSdk.configPDA,
+            admin: admin.publicKey,
+            systemProgram: SystemProgram.programId,
+          })
+          .instruction(),
+        await this.program.methods
+          .closeBackfill()
+          .accounts({
+            config: nsSdk.configPDA,
+            admin: namespaceAdmin.publicKey,
+          })
+          .instruction(),
+      ],
+      [admin, namespaceAdmin]
+    );
+    await assertFails(
+      backfill(namespaceAdmin, 0, nsSdk.configPDA),
+      "BackfillClosed"
+    );
+  });
+
+  it("Queue looser withdraw rate limits", async function (this: CustomContext) {
+    const admin = toWeb3JsKeypair(this.lockerProgramAdmin);
+    const user = this.users[0];
+    const mint = await createFundedMint(this, [user.publicKey], 10);
+    await deposit(this, user, mint, 10);
+    await initMintPolicy(this, mint);
+    const accounts = {
+      config: this.lsdk.configPDA,
+      mintPolicy: this.lsdk.mintPolicyPDA(mint),
+      admin: admin.publicKey,
+    };
+    const setRateLimit = async (windowSlots: number, windowCap: number) =>
+      sendIxs(
+        this,
+        [
+          await this.program.methods
+            .setWithdrawRateLimit(
+              new anchor.BN(windowSlots),
+              new anchor.BN(windowCap)
+            )
+            .accounts(accounts)
+            .instruction(),
+        ],
+        [admin]
+      );
+    const applyRateLimit = async () =>
+      sendIxs(
+        this,
+        [
+          await this.program.methods
+            .applyWithdrawRateLimit()
+            .accounts(accounts)
+            .instruction(),
+        ],
+        [admin]
+      );
+
+    await setRateLimit(1_000_000, 2);
+    await withdraw(this, user, mint, 2);
+
+    // Raising the cap or disabling the limit waits for the delay.
+    await setRateLimit(1_000_000, 5);
+    await assertFails(
+      withdraw(this, user, mint, 1),
+      "WithdrawRateLimitExceeded"
+    );
+    await assertFails(applyRateLimit(), "RateLimitChangeNotReady");
+    await setRateLimit(0, 0);
+    await assertFails(
+      withdraw(this, user, mint, 1),
+      "WithdrawRateLimitExceeded"
+    );
+
+    // A tighter limit applies at once, keeps what was already withdrawn and
+    // drops the queued change.
+    await setRateLimit(2_000_000, 2);
+    await assertFails(
+      withdraw(this, user, mint, 1),
+      "WithdrawRateLimitExceeded"
+    );
+    await assertFails(applyRateLimit(), "RateLimitChangeNotReady");
+    assert.strictEqual(
+      await lockerBalance(this, this.lsdk.getLockerPDA(user.publicKey), mint),
+      8
+    );
+  });
+
+  it("Deposit through scoped delegates", async function (this: CustomContext) {
+    const admin = toWeb3JsKeypair(this.lockerProgramAdmin);
+    const user = this.users[0];
+    const delegate = await createFundedUser(this);
+    const locker = this.lsdk.getLockerPDA(user.publicKey);
+    const mint = await createFundedMint(
+      this,
+      [user.publicKey, delegate.publicKey],
+      10
+    );
+    const [pool, poolBump] = await this.lsdk.withdrawSourcePDA(mint);
+    const [vault, vaultBump] = PublicKey.findProgramAddressSync(
+      [mint.toBuffer(), user.publicKey.toBuffer()],
+      this.program.programId
+    );
+    await sendIxs(
+      this,
+      [
+        await this.program.methods
+          .createDelegate(
+            delegate.publicKey,
+            1,
+            [{ mint, amount: new anchor.BN(3) }],
+            new anchor.BN((await chainTime(this)) + 3600)
+          )
+          .accounts({
+            locker,
+            delegateAccount: this.lsdk.delegatePDA(locker, delegate.publicKey),
+            owner: user.publicKey,
+            systemProgram: SystemProgram.programId,
+          })
+          .instruction(),
+      ],
+      [user]
+    );
+    const delegateDeposit = async (
+      amount: number,
+      lockUntil: anchor.BN | null = null
+    ) => {
+      const { space } = await this.program.account.locker.fetch(locker);
+      const ix = await this.program.methods
+        .depositBatch(
+          [new anchor.BN(amount)],
+          Buffer.from([vaultBump]),
+          Buffer.from([poolBump]),
+          0,
+          space,
+          null,
+          lockUntil
+        )
+        .accounts({
+          config: this.lsdk.configPDA,
+          locker,
+          delegate: this.lsdk.delegatePDA(locker, delegate.publicKey),
+          owner: delegate.publicKey,
+          lockerOwner: user.publicKey,
+          admin: admin.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          rent: SYSVAR_RENT_PUBKEY,
+          treasury: this.lsdk.treasuryPDA(),
+          instructions: SYSVAR_INSTRUCTIONS_PUBKEY,
+        })
+        .remainingAccounts(
+          [
+            mint,
+            getAssociatedTokenAddressSync(mint, delegate.publicKey),
+            vault,
+            pool,
+            this.lsdk.balancePDA(locker, mint),
+          ].map((pubkey) => ({ pubkey, isWritable: true, isSigner: false }))
+        )
+        .instruction();
+      return sendIxs(this, [ix], [delegate, admin]);
+    };
+
+    // Withdrawing leaves an empty legacy vault, filled as older versions of
+    // the program did.
+    await deposit(this, user, mint, 2);
+    await withdraw(this, user, mint, 2);
+    await sendIxs(
+      this,
+      [
+        createTransferInstruction(
+          getAssociatedTokenAddressSync(mint, user.publicKey),
+          vault,
+          user.publicKey,
+          3
+        ),
+      ],
+      [user]
+    );
+
+    // Only the owner can lock items for a season.
+    const lockUntil = new anchor.BN((await chainTime(this)) + 3600);
+    await assertFails(delegateDeposit(1, lockUntil), "Unauthorized");
+
+    // The drained vault rent goes back to the owner, not the delegate.
+    const vaultRent = (await this.connection.getAccountInfo(vault)).lamports;
+    const ownerLamports = await this.connection.getBalance(user.publicKey);
+    await delegateDeposit(1);
+    assert.isNull(await this.connection.getAccountInfo(vault));
+    assert.strictEqual(await lockerBalance(this, locker, mint), 4);
+    assert.strictEqual(
+      await this.connection.getBalance(user.publicKey),
+      ownerLamports + vaultRent
+    );
+  });
 });
@@ -19,6 +19,7 @@ import {
   SystemProgram,
   ConfirmOptions,
 } from "@solana/web3.js";
+import { assert } from "chai";
 
 interface CreateAndSendV0Tx {
   txInstructions: TransactionInstruction[];
@@ -187,3 +188,26 @@ export function log(...args: any[]) {
   const caller = getCallerFileAndLine();
   console.log(`${caller}`, ...args);
 }
+
+export function sleep(ms: number) {
+  return new Promise((resolve) => setTimeout(resolve, ms));
+}
+
+/**
+ * Awaits `promise` and asserts it was rejected with `code`, matched against the
+ * error message and the program logs of the failed simulation.
+ */
+export async function assertFails(promise: Promise<unknown>, code: string) {
+  let error;
+  try {
+    await promise;
+  } catch (e) {
+    error = e;
+  }
+  if (!error) {
+    assert.fail(`Expected the transaction to fail with ${code}`);
+  }
+  const logs: string[] =
+    error.logs ?? error.simulationResponse?.logs ?? error.transactionLogs ?? [];
+  assert.include([error.toString(), ...logs].join("\n"), code);
+}